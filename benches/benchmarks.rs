@@ -1,15 +1,16 @@
 use std::fmt::{Debug, Display};
 use std::sync::Arc;
 
-use criterion::{black_box, criterion_group, criterion_main, BenchmarkId, Criterion};
+use criterion::{black_box, criterion_group, criterion_main, BatchSize, BenchmarkId, Criterion};
 
 use rand::{rngs::SmallRng, Rng, SeedableRng};
-use snork::agents::{maxn, FloodHeuristic, MobilityAgent, TreeHeuristic};
+use snork::agents::{maxn, FloodHeuristic, MobilityAgent, SoloHeuristic, TreeHeuristic};
+use snork::bitboard::BitFloodFill;
 use snork::env::*;
 use snork::floodfill::FloodFill;
-use snork::game::{Game, Outcome, Snake};
+use snork::game::{Game, Mode, Outcome, Snake};
 use snork::logging;
-use snork::search::{self, Heuristic};
+use snork::search::{self, Heuristic, MoveOrdering, SearchContext};
 
 #[derive(Debug, Clone, Default)]
 struct TestH;
@@ -18,7 +19,7 @@ impl Heuristic for TestH {
     fn eval(&self, game: &Game) -> f64 {
         let mut flood_fill = FloodFill::new(game.grid.width, game.grid.height);
         if game.snake_is_alive(0) {
-            flood_fill.flood_snakes(&game.grid, &game.snakes);
+            flood_fill.flood_snakes(game);
             flood_fill.count_space(0) as f64
         } else {
             0.0
@@ -26,6 +27,12 @@ impl Heuristic for TestH {
     }
 }
 
+/// Clears the shared context for the next iteration.
+fn cleared(ctx: &Arc<SearchContext>) -> Arc<SearchContext> {
+    ctx.clear();
+    ctx.clone()
+}
+
 fn game_step_circle(c: &mut Criterion) {
     logging();
     let mut game = Game::parse(
@@ -59,6 +66,35 @@ fn game_step_circle(c: &mut Criterion) {
     });
 }
 
+fn game_apply_undo(c: &mut Criterion) {
+    logging();
+    let mut game = Game::parse(
+        r#"
+            . . . . . . . . . . .
+            . . . . . . . . . . .
+            . . . . 0 > v . . . .
+            . . . . ^ . v . . . .
+            . . . . ^ < < . . . .
+            . . . . . . . . . . .
+            . 1 . . . . . . . . .
+            . ^ . . . . . . . . .
+            . ^ . . . . . . . . .
+            . . . . . . . . . . .
+            . . . . . . . . . . ."#,
+    )
+    .unwrap();
+
+    c.bench_function("game_apply_undo", |b| {
+        b.iter(|| {
+            use Direction::*;
+            let first = game.apply(black_box(&[Right, Up]));
+            let second = game.apply(black_box(&[Right, Right]));
+            game.undo(second);
+            game.undo(first);
+        })
+    });
+}
+
 fn game_step_random(c: &mut Criterion) {
     logging();
     use rand::seq::IteratorRandom;
@@ -112,10 +148,16 @@ fn normal_max_n(c: &mut Criterion) {
         Snake::new(vec![v2(10, 7), v2(10, 6), v2(10, 5)].into(), 100),
         Snake::new(vec![v2(10, 0), v2(9, 0), v2(8, 0)].into(), 100),
     ];
-    let game = Game::new(0, 11, 11, snakes, &[], &[]);
+    let game = Game::new(0, Mode::Standard, 11, 11, snakes, &[], &[]);
 
+    // The context is cleared outside of the measurement
+    let ctx = SearchContext::default();
     c.bench_function("normal_max_n", |b| {
-        b.iter(|| search::max_n(black_box(&game), 2, &TestH))
+        b.iter_batched(
+            || ctx.clear(),
+            |_| search::max_n(black_box(&game), 2, &TestH, &ctx),
+            BatchSize::PerIteration,
+        )
     });
 }
 
@@ -127,11 +169,16 @@ fn async_max_n(c: &mut Criterion) {
         Snake::new(vec![v2(10, 7), v2(10, 6), v2(10, 5)].into(), 100),
         Snake::new(vec![v2(10, 0), v2(9, 0), v2(8, 0)].into(), 100),
     ];
-    let game = Game::new(0, 11, 11, snakes, &[], &[]);
+    let game = Game::new(0, Mode::Standard, 11, 11, snakes, &[], &[]);
 
+    let ctx = Arc::new(SearchContext::default());
     c.bench_function("async_max_n", |b| {
         b.to_async(tokio::runtime::Runtime::new().unwrap())
-            .iter(|| search::async_max_n(black_box(&game), 2, Arc::new(TestH)))
+            .iter_batched(
+                || cleared(&ctx),
+                |ctx| search::async_max_n(black_box(&game), 2, Arc::new(TestH), ctx),
+                BatchSize::PerIteration,
+            )
     });
 }
 
@@ -141,10 +188,15 @@ fn normal_alphabeta(c: &mut Criterion) {
         Snake::new(vec![v2(0, 3), v2(1, 3), v2(2, 3), v2(3, 3)].into(), 100),
         Snake::new(vec![v2(10, 7), v2(10, 6), v2(10, 5)].into(), 100),
     ];
-    let game = Game::new(0, 11, 11, snakes, &[], &[]);
+    let game = Game::new(0, Mode::Standard, 11, 11, snakes, &[], &[]);
 
+    let ctx = SearchContext::default();
     c.bench_function("normal_alphabeta", |b| {
-        b.iter(|| search::alphabeta(black_box(&game), 5, &TestH))
+        b.iter_batched(
+            || ctx.clear(),
+            |_| search::alphabeta(black_box(&game), 5, &TestH, &ctx),
+            BatchSize::PerIteration,
+        )
     });
 }
 
@@ -154,14 +206,61 @@ fn async_alphabeta(c: &mut Criterion) {
         Snake::new(vec![v2(0, 3), v2(1, 3), v2(2, 3), v2(3, 3)].into(), 100),
         Snake::new(vec![v2(10, 7), v2(10, 6), v2(10, 5)].into(), 100),
     ];
-    let game = Game::new(0, 11, 11, snakes, &[], &[]);
+    let game = Game::new(0, Mode::Standard, 11, 11, snakes, &[], &[]);
 
+    let ctx = Arc::new(SearchContext::default());
     c.bench_function("async_alphabeta", |b| {
         b.to_async(tokio::runtime::Runtime::new().unwrap())
-            .iter(|| search::async_alphabeta(black_box(&game), 5, Arc::new(TestH)))
+            .iter_batched(
+                || cleared(&ctx),
+                |ctx| search::async_alphabeta(black_box(&game), 5, Arc::new(TestH), ctx),
+                BatchSize::PerIteration,
+            )
     });
 }
 
+fn alphabeta_ordering(c: &mut Criterion) {
+    logging();
+    let game = Game::parse(
+        r#"
+        . . . . . . . . . . .
+        . . . . . . . . . . .
+        . . . . . . . . . . .
+        . . . . . . . . . . .
+        . . . . . . . . 3 . .
+        . . . . 1 . 0 . ^ . .
+        . . . . ^ . ^ . ^ . .
+        . . . . ^ . ^ . . . .
+        . . . . . . . . . . .
+        . . 2 . . . . . . . .
+        . . ^ . . . . . . . ."#,
+    )
+    .unwrap();
+
+    // Iterative deepening, so that the ordering can learn from the previous iterations
+    let mut group = c.benchmark_group("alphabeta_ordering");
+    group.sample_size(10);
+    for (name, ordering) in [
+        ("fixed", MoveOrdering::fixed as fn() -> MoveOrdering),
+        ("ordered", MoveOrdering::new),
+    ] {
+        let ctx = SearchContext::default().with_ordering(ordering());
+        group.bench_function(name, |b| {
+            b.iter_batched(
+                || ctx.clear(),
+                |_| {
+                    for depth in 1..=3 {
+                        search::alphabeta(black_box(&game), depth, &TestH, &ctx);
+                    }
+                    ctx.nodes()
+                },
+                BatchSize::PerIteration,
+            )
+        });
+    }
+    group.finish()
+}
+
 fn floodfill_normal(c: &mut Criterion) {
     logging();
     let request: GameRequest = serde_json::from_str(
@@ -169,10 +268,45 @@ fn floodfill_normal(c: &mut Criterion) {
         ).unwrap();
 
     let game = Game::from_request(&request);
-    let heuristic = TestH::default();
+    let heuristic = TestH;
     c.bench_function("floodfill_normal", |b| {
         b.iter(|| heuristic.eval(black_box(&game)))
     });
+
+    // Only the fill and the area of both backends, with and without reused buffers
+    let (width, height) = (game.grid.width, game.grid.height);
+    let mut group = c.benchmark_group("floodfill_backend");
+    group.bench_function("floodfill", |b| {
+        b.iter(|| {
+            let mut flood_fill = FloodFill::new(width, height);
+            flood_fill.flood_snakes(black_box(&game));
+            flood_fill.count_space(0)
+        })
+    });
+    group.bench_function("floodfill_cached", |b| {
+        b.iter(|| {
+            FloodFill::with_cached(width, height, |flood_fill| {
+                flood_fill.flood_snakes(black_box(&game));
+                flood_fill.count_space(0)
+            })
+        })
+    });
+    group.bench_function("bitboard", |b| {
+        b.iter(|| {
+            let mut flood_fill = BitFloodFill::new(width, height);
+            flood_fill.flood_snakes(black_box(&game));
+            flood_fill.count_space(0)
+        })
+    });
+    group.bench_function("bitboard_cached", |b| {
+        b.iter(|| {
+            BitFloodFill::with_cached(width, height, |flood_fill| {
+                flood_fill.flood_snakes(black_box(&game));
+                flood_fill.count_space(0)
+            })
+        })
+    });
+    group.finish();
 }
 
 fn tree_heuristic(c: &mut Criterion) {
@@ -187,6 +321,32 @@ fn tree_heuristic(c: &mut Criterion) {
     c.bench_function("tree_heuristic", |b| {
         b.iter(|| heuristic.eval(black_box(&game)))
     });
+
+    // The heuristics with both flood fill backends
+    let mut group = c.benchmark_group("heuristic_backend");
+    for backend in ["FloodFill", "BitBoard"] {
+        let config = format!(r#"{{"flood_fill":"{backend}"}}"#);
+        let heuristics: [(&str, Box<dyn Heuristic>); 3] = [
+            (
+                "flood",
+                Box::new(serde_json::from_str::<FloodHeuristic>(&config).unwrap()),
+            ),
+            (
+                "tree",
+                Box::new(serde_json::from_str::<TreeHeuristic>(&config).unwrap()),
+            ),
+            (
+                "solo",
+                Box::new(serde_json::from_str::<SoloHeuristic>(&config).unwrap()),
+            ),
+        ];
+        for (name, heuristic) in heuristics {
+            group.bench_with_input(BenchmarkId::new(name, backend), &game, |b, game| {
+                b.iter(|| heuristic.eval(black_box(game)))
+            });
+        }
+    }
+    group.finish();
 }
 
 fn tree_search(c: &mut Criterion) {
@@ -198,9 +358,14 @@ fn tree_search(c: &mut Criterion) {
     let game = Game::from_request(&request);
     let heuristic = Arc::new(TreeHeuristic::default());
 
+    let ctx = Arc::new(SearchContext::default());
     c.bench_function("tree_search", |b| {
         b.to_async(tokio::runtime::Runtime::new().unwrap())
-            .iter(|| maxn::tree_search(heuristic.clone(), black_box(&game), 3))
+            .iter_batched(
+                || cleared(&ctx),
+                |ctx| maxn::tree_search(heuristic.clone(), black_box(&game), 3, ctx),
+                BatchSize::PerIteration,
+            )
     });
 }
 
@@ -227,9 +392,14 @@ fn flood_search(c: &mut Criterion) {
     let game = Game::from_request(&request);
     let heuristic = Arc::new(FloodHeuristic::default());
 
+    let ctx = Arc::new(SearchContext::default());
     c.bench_function("flood_search", |b| {
         b.to_async(tokio::runtime::Runtime::new().unwrap())
-            .iter(|| maxn::tree_search(heuristic.clone(), black_box(&game), 3))
+            .iter_batched(
+                || cleared(&ctx),
+                |ctx| maxn::tree_search(heuristic.clone(), black_box(&game), 3, ctx),
+                BatchSize::PerIteration,
+            )
     });
 }
 
@@ -242,9 +412,14 @@ fn flood_2_search(c: &mut Criterion) {
     let game = Game::from_request(&request);
     let heuristic = Arc::new(FloodHeuristic::default());
 
+    let ctx = Arc::new(SearchContext::default());
     c.bench_function("flood_2_search", |b| {
         b.to_async(tokio::runtime::Runtime::new().unwrap())
-            .iter(|| maxn::tree_search(heuristic.clone(), black_box(&game), 6))
+            .iter_batched(
+                || cleared(&ctx),
+                |ctx| maxn::tree_search(heuristic.clone(), black_box(&game), 6, ctx),
+                BatchSize::PerIteration,
+            )
     });
 }
 
@@ -294,11 +469,13 @@ fn math(c: &mut Criterion) {
 criterion_group!(
     benches,
     game_step_circle,
+    game_apply_undo,
     game_step_random,
     async_max_n,
     normal_max_n,
     async_alphabeta,
     normal_alphabeta,
+    alphabeta_ordering,
     floodfill_normal,
     tree_heuristic,
     tree_search,
@@ -0,0 +1,226 @@
+use super::*;
+
+use crate::env::GameRequest;
+use crate::game::{Game, Mode};
+
+/// Properties of a game that the `AutoAgent` uses to select the agent.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Setup<'a> {
+    /// Name of the ruleset (e.g. standard, royale, wrapped)
+    pub ruleset: &'a str,
+    /// Name of the map (e.g. standard, hz_spiral), empty if unknown
+    pub map: &'a str,
+    /// Number of snakes at the start of the game,
+    /// which is unknown if the game is already running
+    pub snakes: Option<usize>,
+    pub width: usize,
+    pub height: usize,
+}
+
+impl<'a> Setup<'a> {
+    /// The snakes are only counted on the first turn,
+    /// because eliminated snakes are missing in later requests.
+    pub fn from_request(request: &'a GameRequest) -> Self {
+        Self {
+            ruleset: &request.game.ruleset.name,
+            map: &request.game.map,
+            snakes: (request.turn == 0).then_some(request.board.snakes.len()),
+            width: request.board.width,
+            height: request.board.height,
+        }
+    }
+
+    /// Approximates the setup from the game state, which does not know about the map,
+    /// and only distinguishes the rulesets with different movement rules.
+    pub fn from_game(game: &Game) -> Self {
+        Self {
+            ruleset: match game.mode {
+                Mode::Standard => "standard",
+                Mode::Wrapped => "wrapped",
+                Mode::Constrictor => "constrictor",
+                Mode::Squad => "squad",
+            },
+            map: "",
+            snakes: (game.turn == 0).then(|| game.snakes.iter().filter(|s| s.alive()).count()),
+            width: game.grid.width,
+            height: game.grid.height,
+        }
+    }
+}
+
+/// Condition under which an agent is selected.
+///
+/// Missing conditions match every game.
+/// Limits of the number of snakes never match, if it is unknown.
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct AutoRule {
+    /// Name of the ruleset
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ruleset: Option<String>,
+    /// Name of the map
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub map: Option<String>,
+    /// Minimal number of snakes
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub min_snakes: Option<usize>,
+    /// Maximal number of snakes
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_snakes: Option<usize>,
+    /// Minimal width and height of the board
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub min_size: Option<usize>,
+    /// Maximal width and height of the board
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_size: Option<usize>,
+    pub agent: Agent,
+}
+
+impl AutoRule {
+    pub fn matches(&self, setup: &Setup) -> bool {
+        let (min_size, max_size) = (setup.width.min(setup.height), setup.width.max(setup.height));
+        self.ruleset.as_ref().is_none_or(|r| r == setup.ruleset)
+            && self.map.as_ref().is_none_or(|m| m == setup.map)
+            && self
+                .min_snakes
+                .is_none_or(|n| setup.snakes.is_some_and(|s| s >= n))
+            && self
+                .max_snakes
+                .is_none_or(|n| setup.snakes.is_some_and(|s| s <= n))
+            && self.min_size.is_none_or(|s| min_size >= s)
+            && self.max_size.is_none_or(|s| max_size <= s)
+    }
+}
+
+/// Meta agent that selects the agent by the first matching rule.
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct AutoAgent {
+    pub rules: Vec<AutoRule>,
+    /// Agent if no rule matches
+    pub default: Box<Agent>,
+}
+
+impl Default for AutoAgent {
+    fn default() -> Self {
+        Self {
+            rules: vec![
+                AutoRule {
+                    ruleset: None,
+                    map: None,
+                    min_snakes: None,
+                    max_snakes: Some(1),
+                    min_size: None,
+                    max_size: None,
+                    agent: Agent::Solo(SoloHeuristic::default()),
+                },
+                AutoRule {
+                    ruleset: Some("royale".into()),
+                    map: None,
+                    min_snakes: None,
+                    max_snakes: None,
+                    min_size: None,
+                    max_size: None,
+                    agent: Agent::Flood(FloodHeuristic::default()),
+                },
+            ],
+            default: Box::new(Agent::Tree(TreeHeuristic::default())),
+        }
+    }
+}
+
+impl AutoAgent {
+    /// Returns the agent of the first matching rule.
+    pub fn select(&self, setup: &Setup) -> &Agent {
+        self.rules
+            .iter()
+            .find(|r| r.matches(setup))
+            .map_or(&*self.default, |r| &r.agent)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::logging;
+
+    #[test]
+    fn auto_agent() {
+        use super::*;
+        logging();
+
+        let setup = |ruleset, map, snakes, size| Setup {
+            ruleset,
+            map,
+            snakes: Some(snakes),
+            width: size,
+            height: size,
+        };
+
+        let agent = Agent::Auto(AutoAgent::default());
+        assert!(matches!(
+            agent.select(&setup("standard", "standard", 1, 11)),
+            Agent::Solo(_)
+        ));
+        assert!(matches!(
+            agent.select(&setup("royale", "royale", 4, 11)),
+            Agent::Flood(_)
+        ));
+        assert!(matches!(
+            agent.select(&setup("standard", "standard", 2, 11)),
+            Agent::Tree(_)
+        ));
+        // Running games with an unknown number of snakes
+        let running = Setup {
+            snakes: None,
+            ..setup("standard", "standard", 1, 11)
+        };
+        assert!(matches!(agent.select(&running), Agent::Tree(_)));
+
+        // Nested agents in the json config
+        let agent: Agent = r#"{"Auto":{
+            "rules":[
+                {"ruleset":"wrapped","min_snakes":3,"agent":{"Mobility":{}}},
+                {"map":"hz_spiral","max_size":11,"agent":{"Auto":{"default":{"Random":null}}}}
+            ],
+            "default":{"Flood":{"space":3.0}}
+        }}"#
+        .parse()
+        .unwrap();
+        assert!(matches!(
+            agent.select(&setup("wrapped", "standard", 4, 11)),
+            Agent::Mobility(_)
+        ));
+        assert!(matches!(
+            agent.select(&setup("wrapped", "standard", 2, 11)),
+            Agent::Flood(_)
+        ));
+        assert!(matches!(
+            agent.select(&setup("standard", "hz_spiral", 2, 11)),
+            Agent::Random(_)
+        ));
+        assert!(matches!(
+            agent.select(&setup("standard", "hz_spiral", 2, 19)),
+            Agent::Flood(_)
+        ));
+        assert_eq!(
+            agent.to_string().parse::<Agent>().unwrap().to_string(),
+            agent.to_string()
+        );
+
+        // The game state only knows the mode and board
+        let game = Game::parse(
+            r#"
+            . . . . .
+            . 0 . . .
+            . ^ . . .
+            . . . . .
+            . . . . ."#,
+        )
+        .unwrap();
+        let setup = Setup::from_game(&game);
+        assert_eq!(
+            (setup.ruleset, setup.map, setup.snakes),
+            ("standard", "", Some(1))
+        );
+    }
+}
@@ -3,47 +3,78 @@ use std::time::Duration;
 use std::time::Instant;
 
 use crate::env::*;
-use crate::game::Game;
-use crate::search::{self, Heuristic};
+use crate::game::{Game, Outcome};
+use crate::metrics::METRICS;
+use crate::search::{self, Heuristic, Search, SearchContext, SearchStats, TimeManager};
 
 use crate::util::argmax;
 
 use log::info;
-use tokio::sync::mpsc;
+use tokio::task::JoinHandle;
 use tokio::time;
 
 const FAST_TIMEOUT: u64 = 150;
 const MAX_DEPTH: usize = 16;
 
 pub async fn step(heuristic: Arc<dyn Heuristic>, timeout: u64, game: &Game) -> MoveResponse {
+    step_ponder(heuristic, timeout, game, None, false, &[])
+        .await
+        .0
+}
+
+/// Like `step`, but reuses the tables of the previous `ponder`, and its results if the game
+/// reached its predicted state, and continues `pondering` on the next predicted state.
+///
+/// The opponents are predicted to play their `likely` moves (by snake index),
+/// or otherwise the replies found by the search.
+/// The returned ponder has to be passed to the next step of the game.
+pub async fn step_ponder(
+    heuristic: Arc<dyn Heuristic>,
+    timeout: u64,
+    game: &Game,
+    ponder: Option<Ponder>,
+    pondering: bool,
+    likely: &[Option<Direction>],
+) -> (MoveResponse, Option<Ponder>) {
     if timeout <= FAST_TIMEOUT {
-        return step_fast(&*heuristic, game);
+        return (step_fast(&*heuristic, game), None);
     }
 
-    let (sender, mut receiver) = mpsc::channel(MAX_DEPTH);
+    let (ctx, cached) = match ponder {
+        Some(ponder) => ponder.stop(game).await,
+        None => (Arc::default(), 0),
+    };
 
-    let _ = time::timeout(
+    let (result, stats) = iterative_tree_search(
+        heuristic.clone(),
+        game,
         Duration::from_millis(timeout),
-        iterative_tree_search(heuristic, game, sender),
+        ctx.clone(),
+        cached,
     )
     .await;
+    info!(">>> {stats:?}");
+    METRICS.search_depth.observe(stats.depth as f64);
 
-    let mut result = None;
-    while let Some(dir) = receiver.recv().await {
-        result = Some(dir);
-    }
-
-    if let Some(dir) = result {
-        return MoveResponse::new(Direction::from(dir as u8));
-    }
+    let dir = result.unwrap_or_else(|| {
+        info!(">>> none");
+        METRICS.fallbacks.inc();
+        game.valid_moves(0).next().unwrap_or(Direction::Up)
+    });
 
-    info!(">>> none");
-    MoveResponse::new(game.valid_moves(0).next().unwrap_or(Direction::Up))
+    let ponder = pondering
+        .then(|| {
+            let timeout = Duration::from_millis(timeout);
+            Ponder::start(heuristic, game, dir, likely, ctx, timeout)
+        })
+        .flatten();
+    (MoveResponse::new(dir), ponder)
 }
 
 pub fn step_fast(heuristic: &dyn Heuristic, game: &Game) -> MoveResponse {
     let start = Instant::now();
-    let result = search::max_n(game, 1, heuristic);
+    // A single turn has no transpositions to reuse
+    let result = search::max_n(game, 1, heuristic, &SearchContext::new(1));
 
     info!(">>> max_n 1 {:?}ms {result:?}", start.elapsed().as_millis());
 
@@ -54,49 +85,348 @@ pub fn step_fast(heuristic: &dyn Heuristic, game: &Game) -> MoveResponse {
     }
 
     info!(">>> none");
+    METRICS.fallbacks.inc();
     MoveResponse::new(game.valid_moves(0).next().unwrap_or(Direction::Up))
 }
 
-async fn iterative_tree_search(
+/// Deepens the tree search until the `timeout` is reached
+/// or the next iteration is not expected to finish in time.
+///
+/// Results of previous searches in the context `ctx` are reused.
+/// Iterations up to the `cached` depth are expected to be answered by the table,
+/// and are not used to predict the duration of the next iterations.
+/// Returns the move of the deepest completed iteration and the search statistics.
+pub async fn iterative_tree_search(
     heuristic: Arc<dyn Heuristic>,
     game: &Game,
-    sender: mpsc::Sender<Direction>,
-) {
+    timeout: Duration,
+    ctx: Arc<SearchContext>,
+    cached: usize,
+) -> (Option<Direction>, SearchStats) {
+    let mut time = TimeManager::new(timeout);
+    let start_nodes = ctx.nodes();
+
+    // Stops the running iteration, including its subtree tasks
+    let canceller = {
+        let ctx = ctx.clone();
+        tokio::spawn(async move {
+            time::sleep(timeout).await;
+            ctx.cancel();
+        })
+    };
+
+    let mut result = None;
+    let mut stats = SearchStats::default();
+
     // Iterative deepening
+    // Results of shallower iterations are reused by the deeper ones
     for depth in 1..MAX_DEPTH {
-        let heuristic = heuristic.clone();
-        let (dir, value) = tree_search(heuristic, game, depth).await;
+        if !time.next_fits() {
+            break;
+        }
+
+        let start = Instant::now();
+        let nodes = ctx.nodes();
+        let (dir, value) = tree_search(heuristic.clone(), game, depth, ctx.clone()).await;
+        if ctx.is_cancelled() {
+            break;
+        }
+        if depth > cached {
+            time.finished(ctx.nodes() - nodes, start.elapsed());
+        }
+        stats.depth = depth;
 
         // Stop and fallback to random possible move
         if value <= search::LOSS {
             break;
         }
 
-        if sender.send(dir).await.is_err()
-            // Terminate if we probably win/lose
-            || value >= search::WIN
-        {
+        result = Some(dir);
+
+        // Terminate if we probably win/lose
+        if value >= search::WIN {
             break;
         }
     }
+
+    canceller.abort();
+    stats.nodes = ctx.nodes() - start_nodes;
+    stats.time = time.elapsed();
+    (result, stats)
+}
+
+/// Search that continues between the turns on the predicted next state.
+///
+/// The opponents are expected to play their most frequent previous moves,
+/// or otherwise their replies from the principal variation of the last search.
+/// If neither is available, their best moves by the history heuristic are used.
+pub struct Ponder {
+    /// Predicted state of the next turn
+    game: Game,
+    ctx: Arc<SearchContext>,
+    task: JoinHandle<SearchStats>,
+}
+
+impl Ponder {
+    /// Starts searching the state after our move `dir` and the most likely replies.
+    ///
+    /// The search stops after the `timeout`, if it is not stopped earlier.
+    pub fn start(
+        heuristic: Arc<dyn Heuristic>,
+        game: &Game,
+        dir: Direction,
+        likely: &[Option<Direction>],
+        ctx: Arc<SearchContext>,
+        timeout: Duration,
+    ) -> Option<Self> {
+        let mut game = game.clone();
+        let moves = Self::predict(&game, dir, likely, &ctx);
+        game.step(&moves);
+        if game.outcome() != Outcome::None {
+            return None;
+        }
+
+        ctx.resume();
+        let task = {
+            let game = game.clone();
+            let ctx = ctx.clone();
+            tokio::spawn(async move {
+                let (_, stats) = iterative_tree_search(heuristic, &game, timeout, ctx, 0).await;
+                info!(">>> ponder {stats:?}");
+                stats
+            })
+        };
+        Some(Self { game, ctx, task })
+    }
+
+    /// Our move, followed by the most likely replies of the opponents.
+    fn predict(
+        game: &Game,
+        dir: Direction,
+        likely: &[Option<Direction>],
+        ctx: &SearchContext,
+    ) -> Vec<Direction> {
+        let mut moves = vec![dir];
+        let pv = ctx.ordering.pv();
+        // The searches freeze and reorder the snakes if there are too many
+        let pv = (game.snakes.len() <= search::MAX_SNAKES && pv.len() >= game.snakes.len())
+            .then(|| &pv[..game.snakes.len()])
+            .filter(|pv| pv[0] == dir);
+        for i in 1..game.snakes.len() {
+            let likely = likely
+                .get(i)
+                .copied()
+                .flatten()
+                .filter(|&d| game.move_is_valid(i as u8, d));
+            moves.push(match (likely, pv) {
+                (Some(d), _) => d,
+                (None, Some(pv)) => pv[i],
+                (None, None) => ctx.ordering.order(game, i as u8, &[], None)[0],
+            });
+        }
+        moves
+    }
+
+    /// Predicted state of the next turn.
+    pub fn game(&self) -> &Game {
+        &self.game
+    }
+
+    /// Stops the search and returns its context,
+    /// which is reused for the next search, and the completed depth,
+    /// which is zero if the `game` did not reach the predicted state.
+    ///
+    /// If the hazards changed, the cached values are cleared too.
+    pub async fn stop(mut self, game: &Game) -> (Arc<SearchContext>, usize) {
+        self.ctx.cancel();
+        let depth = (&mut self.task).await.map_or(0, |stats| stats.depth);
+        self.ctx.resume();
+        // Hazards are not part of the hash, but change the values of all states
+        let grid = &self.game.grid;
+        let same_hazards = game.grid.width == grid.width
+            && game.grid.height == grid.height
+            && (game.grid.cells.iter())
+                .zip(&grid.cells)
+                .all(|(a, b)| a.hazards == b.hazards);
+        if !same_hazards {
+            info!(">>> ponder miss (hazards)");
+            self.ctx.tt.clear();
+            return (self.ctx.clone(), 0);
+        }
+        if game.hash() != self.game.hash() {
+            info!(">>> ponder miss");
+            return (self.ctx.clone(), 0);
+        }
+        info!(">>> ponder hit {depth}");
+        (self.ctx.clone(), depth)
+    }
+}
+
+impl Drop for Ponder {
+    fn drop(&mut self) {
+        if !self.task.is_finished() {
+            self.ctx.cancel();
+        }
+    }
 }
 
-/// Performes a tree search and returns the maximized heuristic and move.
+/// Performes a tree search, with the search algorithm of the heuristic,
+/// and returns the maximized heuristic and move.
 pub async fn tree_search(
     heuristic: Arc<dyn Heuristic>,
     game: &Game,
     depth: usize,
+    ctx: Arc<SearchContext>,
 ) -> (Direction, f64) {
     let start = Instant::now();
 
-    let result = search::async_max_n(game, depth, heuristic).await;
+    match heuristic.search() {
+        Search::MaxN => {
+            let result = search::async_max_n(game, depth, heuristic, ctx).await;
+
+            info!(
+                ">>> max_n {depth} {:?}ms {result:?}",
+                start.elapsed().as_millis(),
+            );
+
+            argmax(result.iter())
+                .map(|d| (Direction::from(d as u8), result[d]))
+                .unwrap()
+        }
+        Search::AlphaBeta => {
+            let result = search::async_alphabeta(game, depth, heuristic, ctx).await;
+
+            info!(
+                ">>> alphabeta {depth} {:?}ms {result:?}",
+                start.elapsed().as_millis(),
+            );
+
+            result
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use log::info;
 
-    info!(
-        ">>> max_n {depth} {:?}ms {result:?}",
-        start.elapsed().as_millis(),
-    );
+    use crate::logging;
 
-    argmax(result.iter())
-        .map(|d| (Direction::from(d as u8), result[d]))
-        .unwrap()
+    #[derive(Debug, Clone, Default)]
+    struct TestH;
+    impl super::Heuristic for TestH {
+        fn eval(&self, game: &super::Game) -> f64 {
+            game.valid_moves(0).count() as f64
+        }
+        fn search(&self) -> super::Search {
+            super::Search::AlphaBeta
+        }
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn ponder() {
+        use super::*;
+        logging();
+
+        let game = Game::parse(
+            r#"
+            . . . . . . . . . . .
+            . . . . . . . . . . .
+            . . . . . . . . . . .
+            . . . 0 . . . . . . .
+            . . . ^ . . . . . . .
+            . . . ^ . . . . . . .
+            . . . . . . . . . . .
+            . . . . . . . 1 . . .
+            . . . . . . . ^ . . .
+            . . . . . . . ^ . . .
+            . . . . . . . . . . ."#,
+        )
+        .unwrap();
+
+        let (response, ponder) = step_ponder(Arc::new(TestH), 200, &game, None, true, &[]).await;
+        let ponder = ponder.unwrap();
+        let predicted = ponder.game().clone();
+        info!("{response:?} {:?}", predicted.grid);
+        assert_eq!(predicted.turn, game.turn + 1);
+        assert_eq!(
+            predicted.snakes[0].head(),
+            game.snakes[0].head().apply(response.r#move)
+        );
+
+        // The tables of the predicted state are reused
+        time::sleep(Duration::from_millis(100)).await;
+        let (ctx, depth) = ponder.stop(&predicted).await;
+        info!("ponder depth {depth}");
+        assert!(depth >= 1);
+        assert!(!ctx.is_cancelled());
+        // The root entry may be replaced by a colliding state, but not by a shallower one
+        if let Some(entry) = ctx.tt.get(predicted.hash()) {
+            assert!(entry.depth >= depth);
+        }
+        let pv = ctx.ordering.pv();
+        assert!(!pv.is_empty() && predicted.move_is_valid(0, pv[0]));
+
+        // Changed hazards are a miss and clear the tables
+        let (_, ponder) = step_ponder(Arc::new(TestH), 200, &game, None, true, &[]).await;
+        let ponder = ponder.unwrap();
+        let mut hazards = ponder.game().clone();
+        hazards.grid.add_hazards(&[Vec2D::new(10, 10)]);
+        assert_eq!(hazards.hash(), ponder.game().hash());
+        time::sleep(Duration::from_millis(50)).await;
+        let (ctx, depth) = ponder.stop(&hazards).await;
+        assert_eq!(depth, 0);
+        assert_eq!(ctx.tt.get(hazards.hash()), None);
+
+        // Other states are searched again, with the same tables
+        let (_, ponder) = step_ponder(Arc::new(TestH), 200, &predicted, None, true, &[]).await;
+        let ponder = ponder.unwrap();
+        let tt = Arc::as_ptr(&ponder.ctx);
+        let (ctx, depth) = ponder.stop(&game).await;
+        assert_eq!(depth, 0);
+        assert_eq!(Arc::as_ptr(&ctx), tt);
+        assert!(!ctx.is_cancelled());
+    }
+
+    #[test]
+    fn ponder_predict() {
+        use super::*;
+        logging();
+
+        let game = Game::parse(
+            r#"
+            . . . . . . .
+            . . . . . . .
+            . 0 . . . . .
+            . ^ . . . 1 .
+            . ^ . . . ^ .
+            . . . . . ^ .
+            . . . . . . ."#,
+        )
+        .unwrap();
+        let ctx = SearchContext::default();
+
+        // The most frequent move of the opponent, if it is valid
+        let moves = Ponder::predict(&game, Direction::Up, &[None, Some(Direction::Left)], &ctx);
+        assert_eq!(moves, [Direction::Up, Direction::Left]);
+        // Otherwise by the move ordering
+        let moves = Ponder::predict(&game, Direction::Up, &[None, Some(Direction::Down)], &ctx);
+        assert_eq!(moves.len(), 2);
+        assert_ne!(moves[1], Direction::Down);
+
+        // The most frequent move is preferred over the principal variation
+        ctx.ordering.set_pv(&[Direction::Up, Direction::Right]);
+        let moves = Ponder::predict(&game, Direction::Up, &[None, Some(Direction::Left)], &ctx);
+        assert_eq!(moves, [Direction::Up, Direction::Left]);
+        // which is used for invalid or unknown moves
+        for likely in [None, Some(Direction::Down)] {
+            let moves = Ponder::predict(&game, Direction::Up, &[None, likely], &ctx);
+            assert_eq!(moves, [Direction::Up, Direction::Right]);
+        }
+        // if it starts with our move
+        let moves = Ponder::predict(&game, Direction::Left, &[None, None], &ctx);
+        let ordered = ctx.ordering.order(&game, 1, &[], None)[0];
+        assert_ne!(ordered, Direction::Right);
+        assert_eq!(moves, [Direction::Left, ordered]);
+    }
 }
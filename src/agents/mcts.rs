@@ -0,0 +1,331 @@
+use std::time::{Duration, Instant};
+
+use log::info;
+use rand::seq::IteratorRandom;
+use rand::{rngs::SmallRng, Rng, SeedableRng};
+
+use crate::bitboard::{BitFloodFill, MAX_CELLS};
+use crate::env::*;
+use crate::floodfill::FloodFill;
+use crate::game::{Game, Outcome};
+
+/// Policy for the simulated moves after leaving the search tree.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, serde::Serialize, serde::Deserialize)]
+pub enum Playout {
+    /// Completely random moves, surviving snakes share the reward equally.
+    Random,
+    /// Random valid moves, surviving snakes are rewarded by their flood fill area.
+    #[default]
+    Guided,
+}
+
+/// Monte Carlo tree search for simultaneous moves (decoupled UCT).
+///
+/// Every snake selects its move independently with UCB1 on its own move statistics,
+/// and the resulting joint move leads to the next node of the tree.
+/// Unlike the tree searches, the opponents are not assumed to minimize our score,
+/// but to maximize their own.
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct MctsAgent {
+    /// UCB1 exploration constant
+    exploration: f64,
+    playout: Playout,
+    /// Maximum number of simulated turns per playout
+    playout_depth: usize,
+    /// Rate the guided playouts with the faster bitboard flood fill,
+    /// which ignores food and hazards.
+    /// Boards without them always use it, as it is exact there.
+    bitboard: bool,
+}
+
+impl Default for MctsAgent {
+    fn default() -> Self {
+        Self {
+            exploration: 1.4,
+            playout: Playout::Guided,
+            playout_depth: 8,
+            bitboard: false,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+struct Stat {
+    visits: u32,
+    reward: f64,
+}
+
+#[derive(Debug, Default)]
+struct Node {
+    visits: u32,
+    /// Statistics of the four moves for every snake
+    stats: Vec<[Stat; 4]>,
+    /// Child nodes by the joint moves of all snakes
+    children: Vec<(u64, usize)>,
+}
+
+impl Node {
+    fn new(snakes: usize) -> Self {
+        Self {
+            visits: 0,
+            stats: vec![[Stat::default(); 4]; snakes],
+            children: Vec::new(),
+        }
+    }
+}
+
+/// Encodes the moves of all snakes into a single key.
+fn joint_key(moves: &[Direction]) -> u64 {
+    moves
+        .iter()
+        .enumerate()
+        .fold(0, |key, (i, &d)| key | (d as u64) << (2 * i))
+}
+
+impl MctsAgent {
+    pub async fn step(&self, timeout: u64, game: &Game) -> MoveResponse {
+        let agent = self.clone();
+        let game = game.clone();
+        let start = Instant::now();
+
+        let result = tokio::task::spawn_blocking(move || {
+            let mut rng = SmallRng::from_entropy();
+            let visits = agent.search(&game, Duration::from_millis(timeout), usize::MAX, &mut rng);
+            (visits, game.valid_moves(0).next())
+        })
+        .await;
+
+        if let Ok((visits, fallback)) = result {
+            info!(">>> mcts {:?}ms {visits:?}", start.elapsed().as_millis());
+            // Most visited move
+            if let Some(dir) = (0..4).filter(|&d| visits[d] > 0).max_by_key(|&d| visits[d]) {
+                return MoveResponse::new(Direction::from(dir as u8));
+            }
+            if let Some(dir) = fallback {
+                return MoveResponse::new(dir);
+            }
+        }
+
+        info!(">>> none");
+        MoveResponse::new(Direction::Up)
+    }
+
+    /// Searches until the `timeout` or the number of `iterations` is reached
+    /// and returns how often each move of the snake 0 has been visited.
+    pub fn search(
+        &self,
+        game: &Game,
+        timeout: Duration,
+        iterations: usize,
+        rng: &mut impl Rng,
+    ) -> [u32; 4] {
+        let start = Instant::now();
+        let snakes = game.snakes.len();
+        let mut nodes = vec![Node::new(snakes)];
+
+        let mut path = Vec::new();
+        let mut moves = vec![Direction::Up; snakes];
+        let mut n = 0;
+        while n < iterations && start.elapsed() < timeout {
+            n += 1;
+            let mut game = game.clone();
+            path.clear();
+
+            // Selection & expansion
+            let mut node = 0;
+            while game.outcome() == Outcome::None {
+                for (i, mv) in moves.iter_mut().enumerate() {
+                    *mv = self.select(&nodes[node], &game, i as u8);
+                }
+                path.push((node, moves.clone()));
+                game.step(&moves);
+
+                let key = joint_key(&moves);
+                if let Some(&(_, child)) = nodes[node].children.iter().find(|c| c.0 == key) {
+                    node = child;
+                } else {
+                    nodes.push(Node::new(snakes));
+                    let child = nodes.len() - 1;
+                    nodes[node].children.push((key, child));
+                    node = child;
+                    break;
+                }
+            }
+
+            let rewards = self.playout(&mut game, rng);
+
+            // Backpropagation
+            nodes[node].visits += 1;
+            for (node, moves) in &path {
+                let node = &mut nodes[*node];
+                node.visits += 1;
+                for ((stats, &d), &reward) in node.stats.iter_mut().zip(moves).zip(&rewards) {
+                    let stat = &mut stats[d as usize];
+                    stat.visits += 1;
+                    stat.reward += reward;
+                }
+            }
+        }
+
+        info!("mcts: {n} iterations, {} nodes", nodes.len());
+        nodes[0].stats[0].map(|s| s.visits)
+    }
+
+    /// Selects the move of a snake with UCB1, trying unvisited moves first.
+    fn select(&self, node: &Node, game: &Game, snake: u8) -> Direction {
+        if !game.snake_is_alive(snake) {
+            return Direction::Up;
+        }
+        let stats = &node.stats[snake as usize];
+        let ln_visits = (node.visits.max(1) as f64).ln();
+        let ucb = |d: Direction| {
+            let stat = stats[d as usize];
+            if stat.visits == 0 {
+                f64::INFINITY
+            } else {
+                stat.reward / stat.visits as f64
+                    + self.exploration * (ln_visits / stat.visits as f64).sqrt()
+            }
+        };
+        game.valid_moves(snake)
+            .max_by(|&a, &b| ucb(a).total_cmp(&ucb(b)))
+            .unwrap_or(Direction::Up)
+    }
+
+    /// Simulates the game with the playout policy and returns the rewards of the snakes.
+    fn playout(&self, game: &mut Game, rng: &mut impl Rng) -> Vec<f64> {
+        let mut moves = vec![Direction::Up; game.snakes.len()];
+        for _ in 0..self.playout_depth {
+            if game.outcome() != Outcome::None {
+                break;
+            }
+            for (i, mv) in moves.iter_mut().enumerate() {
+                *mv = match self.playout {
+                    Playout::Random => Direction::from(rng.gen_range(0..4)),
+                    Playout::Guided => game
+                        .valid_moves(i as u8)
+                        .choose(rng)
+                        .unwrap_or(Direction::Up),
+                };
+            }
+            game.step(&moves);
+        }
+        self.rewards(game)
+    }
+
+    /// Winners get 1, losers 0, and surviving snakes share the reward.
+    fn rewards(&self, game: &Game) -> Vec<f64> {
+        let snakes = &game.snakes;
+        match game.outcome() {
+            Outcome::Winner(w) => snakes
+                .iter()
+                .enumerate()
+                .map(|(i, s)| (i == w as usize || s.allied(&snakes[w as usize])) as u8 as f64)
+                .collect(),
+            Outcome::Match => vec![0.5; snakes.len()],
+            Outcome::None => {
+                let mut space = snakes
+                    .iter()
+                    .map(|s| s.alive() as u8 as f64)
+                    .collect::<Vec<_>>();
+                if self.playout == Playout::Guided {
+                    let (width, height) = (game.grid.width, game.grid.height);
+                    let bitboard = self.bitboard && width * height <= MAX_CELLS;
+                    // The occupied head counts too, so that trapped snakes are not zero
+                    if bitboard || BitFloodFill::is_exact(game) {
+                        BitFloodFill::with_cached(width, height, |flood_fill| {
+                            flood_fill.flood_snakes(game);
+                            for (i, space) in space.iter_mut().enumerate() {
+                                *space *= flood_fill.count_space(i as u8) as f64 + 1.0;
+                            }
+                        });
+                    } else {
+                        FloodFill::with_cached(width, height, |flood_fill| {
+                            flood_fill.flood_snakes(game);
+                            for (i, space) in space.iter_mut().enumerate() {
+                                *space *= flood_fill.count_space(i as u8) as f64 + 1.0;
+                            }
+                        });
+                    }
+                }
+                let total: f64 = space.iter().sum();
+                space.iter().map(|s| s / total.max(1.0)).collect()
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use log::info;
+
+    use crate::logging;
+
+    #[test]
+    fn mcts_avoid_trap() {
+        use super::*;
+        logging();
+
+        let game = Game::parse(
+            r#"
+            . . . . . . . . . . .
+            . . . . . . . . . . .
+            . . . . . . . . 1 . .
+            . . . . . . . . ^ . .
+            . . . . . . . . ^ . .
+            . . . . . . . . . . .
+            . . . . . . . . . . .
+            . . . . . . . . . . .
+            . . . . . . . . . . .
+            0 < . . . . . . . . .
+            . ^ < < < < . . . . ."#,
+        )
+        .unwrap();
+
+        // Down leads into a dead end, up is open
+        for playout in [Playout::Random, Playout::Guided] {
+            let agent = MctsAgent {
+                playout,
+                ..MctsAgent::default()
+            };
+            let mut rng = SmallRng::seed_from_u64(42);
+            let visits = agent.search(&game, Duration::from_secs(60), 2000, &mut rng);
+            info!("{playout:?}: {visits:?}");
+            assert_eq!(visits.iter().sum::<u32>(), 2000);
+            let best = (0..4).max_by_key(|&d| visits[d]).unwrap();
+            assert_eq!(Direction::from(best as u8), Direction::Up);
+        }
+    }
+
+    #[test]
+    fn mcts_rewards() {
+        use super::*;
+        logging();
+
+        let mut game = Game::parse(
+            r#"
+            . . . . .
+            . . . . .
+            0 < . . 1
+            . . . . ^
+            . . . . ."#,
+        )
+        .unwrap();
+
+        let agent = MctsAgent::default();
+        let rewards = agent.rewards(&game);
+        assert_eq!(rewards.iter().sum::<f64>(), 1.0);
+
+        // Without food and hazards, the bitboard flood fill is the same
+        let bitboard = MctsAgent {
+            bitboard: true,
+            ..MctsAgent::default()
+        };
+        assert_eq!(bitboard.rewards(&game), rewards);
+
+        // The snake 1 runs into the wall
+        game.step(&[Direction::Up, Direction::Right]);
+        assert_eq!(agent.rewards(&game), vec![1.0, 0.0]);
+    }
+}
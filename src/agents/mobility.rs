@@ -8,7 +8,8 @@ use crate::env::*;
 use crate::floodfill::{FCell, FloodFill};
 use crate::game::{Game, Snake};
 use crate::grid::CellT;
-use crate::search::{self, Heuristic};
+use crate::metrics::METRICS;
+use crate::search::{self, Heuristic, SearchContext};
 use crate::util::{argmax, OrdPair};
 
 #[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
@@ -40,7 +41,7 @@ impl Heuristic for MobilityHeuristic {
     fn eval(&self, game: &Game) -> f64 {
         if game.snake_is_alive(0) {
             let mut flood_fill = FloodFill::new(game.grid.width, game.grid.width);
-            flood_fill.flood_snakes(&game.grid, &game.snakes);
+            flood_fill.flood_snakes(game);
             flood_fill.count_space(0) as f64
         } else {
             0.0
@@ -66,11 +67,8 @@ impl MobilityAgent {
         let mut grid = game.grid.clone();
         for snake in &game.snakes[1..] {
             if snake.body.len() >= you.body.len() {
-                for d in Direction::iter() {
-                    let p = snake.head().apply(d);
-                    if grid.has(p) {
-                        grid[p].t = CellT::Owned;
-                    }
+                for p in Direction::iter().flat_map(|d| game.grid.neighbor(snake.head(), d)) {
+                    grid[p].t = CellT::Owned;
                 }
             }
         }
@@ -94,7 +92,7 @@ impl MobilityAgent {
                             FCell::Owned { id: 0, .. } => 0,
                             _ => 5,
                         };
-                    food_dirs.push(OrdPair(Reverse(costs), Direction::from(path[1] - path[0])));
+                    food_dirs.push(OrdPair(Reverse(costs), grid.direction(path[0], path[1])));
                 }
             }
         }
@@ -113,14 +111,14 @@ impl MobilityAgent {
 
         // Flood fill heuristics
         let start = Instant::now();
-        let space_after_move = search::max_n(game, 1, &MobilityHeuristic);
+        let space_after_move = search::max_n(game, 1, &MobilityHeuristic, &SearchContext::new(1));
         info!(
             "max_n {:?}ms {space_after_move:?}",
             start.elapsed().as_millis()
         );
 
         let mut flood_fill = FloodFill::new(game.grid.width, game.grid.height);
-        flood_fill.flood_snakes(&game.grid, &game.snakes);
+        flood_fill.flood_snakes(game);
 
         // Find Food
         if you.body.len() < self.min_len || you.health < self.health_threshold {
@@ -139,6 +137,7 @@ impl MobilityAgent {
         }
 
         warn!(">>> random");
+        METRICS.fallbacks.inc();
         MoveResponse::new(game.valid_moves(0).next().unwrap_or(Direction::Up))
     }
 }
@@ -1,5 +1,5 @@
+use std::fmt;
 use std::str::FromStr;
-use std::string::ToString;
 use std::sync::Arc;
 
 mod original;
@@ -11,14 +11,22 @@ pub use flood::*;
 mod random;
 pub use random::*;
 pub mod maxn;
+mod mcts;
+pub use mcts::*;
 mod solo;
 pub use solo::*;
+mod auto;
+pub use auto::*;
 
 use crate::game::Game;
+use crate::metrics::METRICS;
+use crate::search::Heuristic;
+use crate::session::Session;
 
 use super::env::{GameRequest, MoveResponse};
 
-const MAX_BOARD_SIZE: usize = 19;
+/// Larger boards are played randomly (the arcade maze is the largest with 19x21).
+pub const MAX_BOARD_SIZE: usize = 21;
 
 #[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
 #[serde(deny_unknown_fields)]
@@ -28,6 +36,9 @@ pub enum Agent {
     Flood(FloodHeuristic),
     Solo(SoloHeuristic),
     Random(RandomAgent),
+    Mcts(MctsAgent),
+    /// Selects one of the other agents depending on the game
+    Auto(AutoAgent),
 }
 
 impl Default for Agent {
@@ -37,24 +48,90 @@ impl Default for Agent {
 }
 
 impl Agent {
+    /// Name of the agent variant.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Agent::Mobility(_) => "Mobility",
+            Agent::Tree(_) => "Tree",
+            Agent::Flood(_) => "Flood",
+            Agent::Solo(_) => "Solo",
+            Agent::Random(_) => "Random",
+            Agent::Mcts(_) => "Mcts",
+            Agent::Auto(_) => "Auto",
+        }
+    }
+
+    /// Returns the agent that plays the game, which is only different for `Auto`.
+    pub fn select(&self, setup: &Setup) -> &Agent {
+        match self {
+            Agent::Auto(agent) => agent.select(setup).select(setup),
+            agent => agent,
+        }
+    }
+
     pub async fn step(&self, request: &GameRequest, latency: u64) -> MoveResponse {
         let game = Game::from_request(request);
         let timeout = request.game.timeout.saturating_sub(latency);
 
-        self.step_internal(timeout, &game).await
+        let agent = self.select(&Setup::from_request(request));
+        agent.step_internal(timeout, &game).await
     }
 
+    /// Like `step`, but records the game in the `session`,
+    /// and the tree search agents may continue searching between the turns.
+    pub async fn step_session(
+        &self,
+        request: &GameRequest,
+        latency: u64,
+        session: &mut Session,
+    ) -> MoveResponse {
+        let game = Game::from_request(request);
+        let timeout = request.game.timeout.saturating_sub(latency);
+        session.update(request, &game);
+
+        let agent = self.select(&Setup::from_request(request));
+        let heuristic: Arc<dyn Heuristic> = match agent {
+            _ if game.grid.width > MAX_BOARD_SIZE || game.grid.height > MAX_BOARD_SIZE => {
+                METRICS.fallbacks.inc();
+                return RandomAgent.step(&game).await;
+            }
+            Agent::Tree(agent) => Arc::new(agent.clone()),
+            Agent::Flood(agent) => Arc::new(agent.clone()),
+            Agent::Solo(agent) => Arc::new(agent.clone()),
+            _ => return agent.step_internal(timeout, &game).await,
+        };
+
+        let ponder = session.ponder.take();
+        let likely = session.likely_moves(request);
+        let (response, ponder) = maxn::step_ponder(
+            heuristic,
+            timeout,
+            &game,
+            ponder,
+            session.pondering,
+            &likely,
+        )
+        .await;
+        session.ponder = ponder;
+        response
+    }
+
+    /// Steps the agent without the request,
+    /// so `Auto` selects its agent only by the game state.
     pub async fn step_internal(&self, timeout: u64, game: &Game) -> MoveResponse {
         if game.grid.width > MAX_BOARD_SIZE || game.grid.height > MAX_BOARD_SIZE {
+            METRICS.fallbacks.inc();
             return RandomAgent.step(game).await;
         }
 
-        match self {
+        match self.select(&Setup::from_game(game)) {
             Agent::Mobility(agent) => agent.step(game).await,
             Agent::Tree(agent) => maxn::step(Arc::new(agent.clone()), timeout, game).await,
             Agent::Flood(agent) => maxn::step(Arc::new(agent.clone()), timeout, game).await,
             Agent::Solo(agent) => maxn::step(Arc::new(agent.clone()), timeout, game).await,
             Agent::Random(agent) => agent.step(game).await,
+            Agent::Mcts(agent) => agent.step(timeout, game).await,
+            Agent::Auto(_) => unreachable!("resolved by select"),
         }
     }
 }
@@ -67,8 +144,8 @@ impl FromStr for Agent {
     }
 }
 
-impl ToString for Agent {
-    fn to_string(&self) -> String {
-        serde_json::to_string(self).unwrap_or_default()
+impl fmt::Display for Agent {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", serde_json::to_string(self).unwrap_or_default())
     }
 }
@@ -1,7 +1,7 @@
 use crate::env::*;
-use crate::floodfill::FloodFill;
+use crate::floodfill::FloodBackend;
 use crate::game::Game;
-use crate::search::{self, Heuristic};
+use crate::search::{self, Heuristic, Search};
 
 /// Configuration of the tree search heuristic.
 #[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
@@ -17,6 +17,8 @@ pub struct TreeHeuristic {
     food_ownership_decay: f64,
     centrality: f64,
     centrality_decay: f64,
+    search: Search,
+    flood_fill: FloodBackend,
 }
 
 impl Default for TreeHeuristic {
@@ -32,20 +34,26 @@ impl Default for TreeHeuristic {
             food_ownership_decay: 0.0,
             centrality: 0.1,
             centrality_decay: 0.0,
+            search: Search::MaxN,
+            flood_fill: FloodBackend::FloodFill,
         }
     }
 }
 
 impl Heuristic for TreeHeuristic {
+    fn search(&self) -> Search {
+        self.search
+    }
+
     /// Heuristic function for the tree search.
     fn eval(&self, game: &Game) -> f64 {
         if !game.snake_is_alive(0) {
             return search::LOSS;
         }
 
-        let mut flood_fill = FloodFill::new(game.grid.width, game.grid.height);
-        let food_distances = flood_fill.flood_snakes(&game.grid, &game.snakes);
-        let space = flood_fill.count_space(0);
+        let (accessable_food, space) = self.flood_fill.flood(game, |flood_fill, food_distances| {
+            (food_distances.len().min(4), flood_fill.count_space(0))
+        });
         let mobility = space as f64 / (game.grid.width * game.grid.height) as f64;
 
         let health = game.snakes[0].health as f64 / 100.0;
@@ -54,14 +62,14 @@ impl Heuristic for TreeHeuristic {
         let own_len = game.snakes[0].body.len();
         let max_enemy_len = game.snakes[1..]
             .iter()
+            .filter(|s| !s.allied(&game.snakes[0]))
             .map(|s| s.body.len())
             .max()
             .unwrap_or(0);
         let len_advantage = own_len as f64 / max_enemy_len as f64;
 
         // Owned food
-        let accessable_food = food_distances.iter().count() as f64;
-        let food_ownership = accessable_food / game.grid.width as f64;
+        let food_ownership = accessable_food as f64 / game.grid.width as f64;
 
         // Centrality
         let centrality = 1.0
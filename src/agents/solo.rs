@@ -1,6 +1,6 @@
-use crate::floodfill::FloodFill;
+use crate::floodfill::FloodBackend;
 use crate::game::Game;
-use crate::search::{self, Heuristic};
+use crate::search::{self, Heuristic, Search};
 
 /// The new floodfill agent for royale games
 #[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
@@ -9,6 +9,8 @@ pub struct SoloHeuristic {
     saturated: f64,
     space: f64,
     size: f64,
+    search: Search,
+    flood_fill: FloodBackend,
 }
 
 impl Default for SoloHeuristic {
@@ -17,27 +19,34 @@ impl Default for SoloHeuristic {
             saturated: 0.1,
             space: 1.0,
             size: 0.5,
+            search: Search::MaxN,
+            flood_fill: FloodBackend::FloodFill,
         }
     }
 }
 
 impl Heuristic for SoloHeuristic {
+    fn search(&self) -> Search {
+        self.search
+    }
+
     fn eval(&self, game: &Game) -> f64 {
         if game.snake_is_alive(0) {
             let you = &game.snakes[0];
             let area = (game.grid.width * game.grid.height) as f64;
 
-            let mut flood_fill = FloodFill::new(game.grid.width, game.grid.height);
-            let food_distances = flood_fill.flood_snakes(&game.grid, &game.snakes);
+            let (food_distance, space) =
+                self.flood_fill.flood(game, |flood_fill, food_distances| {
+                    (food_distances.first().copied(), flood_fill.count_space(0))
+                });
 
-            let food_distance = food_distances[0] as f64;
-            let saturated = if food_distance < you.health as f64 {
-                1.0
-            } else {
-                0.0
+            // Without reachable food, we are not saturated
+            let saturated = match food_distance {
+                Some(d) if (d as f64) < you.health as f64 => 1.0,
+                _ => 0.0,
             };
 
-            let space = flood_fill.count_space(0) as f64 / area;
+            let space = space as f64 / area;
             let size = (3.0 / you.body.len() as f64).sqrt();
 
             self.saturated * saturated + self.space * space + self.size * size
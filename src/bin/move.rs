@@ -41,7 +41,7 @@ async fn main() {
     info!("{game:?}");
 
     let mut flood_fill = FloodFill::new(request.board.width, request.board.height);
-    flood_fill.flood_snakes(&game.grid, &game.snakes);
+    flood_fill.flood_snakes(&game);
     info!("{flood_fill:?}");
 
     let step = config.step(&request, latency as _).await;
@@ -1,26 +1,16 @@
-use std::convert::Infallible;
+use std::collections::HashMap;
 use std::net::SocketAddr;
-use std::sync::Arc;
-use std::time::Instant;
+use std::path::PathBuf;
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
 
-use log::{info, warn};
 use snork::agents::*;
-use snork::env::{GameRequest, IndexResponse, API_VERSION};
+use snork::latency::LatencyConfig;
+use snork::savegame::Recorder;
+use snork::server::{self, load_snakes, watch_snakes, Snake, State};
+use snork::session::SessionStore;
 
 use clap::Parser;
-use warp::Filter;
-
-pub const PACKAGE_VERSION: &str = env!("CARGO_PKG_VERSION");
-pub const AUTHOR: &str = "l4r0x";
-
-/// Runtime server configuration.
-struct State {
-    latency: u64,
-    color: String,
-    head: String,
-    tail: String,
-    config: Agent,
-}
 
 #[derive(Debug, Parser)]
 #[clap(version, author, about = "High performant rust snake.")]
@@ -32,8 +22,20 @@ struct Opt {
     #[clap(long, default_value = "127.0.0.1:5001", value_parser)]
     host: SocketAddr,
     /// Time in ms that is subtracted from the game timeouts.
+    ///
+    /// With `--adaptive-latency`, this is only used until the latency of the game is known,
+    /// and limits estimates from the time between the requests (including the margin).
     #[clap(long, default_value_t = 100, value_parser)]
     latency: u64,
+    /// Estimate the latency of each game from the previous turns.
+    #[clap(long, value_parser)]
+    adaptive_latency: bool,
+    /// Time in ms that is added to the estimated latency.
+    #[clap(long, default_value_t = 20, value_parser)]
+    latency_margin: u64,
+    /// Minimal time in ms that is subtracted from the game timeouts.
+    #[clap(long, default_value_t = 10, value_parser)]
+    latency_floor: u64,
     /// Color in hex format.
     #[clap(long, default_value = "#FF7043", value_parser)]
     color: String,
@@ -46,6 +48,28 @@ struct Opt {
     /// Default configuration.
     #[clap(long, default_value_t, value_parser)]
     config: Agent,
+    /// Continue searching between the turns on the predicted next state.
+    #[clap(long, value_parser)]
+    ponder: bool,
+    /// Time in s after which games without requests are dropped.
+    #[clap(long, default_value_t = 600, value_parser)]
+    session_ttl: u64,
+    /// Directory where the games are recorded as JSON Lines files.
+    #[clap(long, value_parser)]
+    record_dir: Option<PathBuf>,
+    /// JSON file with additional snakes that are served at `/<name>/`.
+    ///
+    /// It maps the names to the agent `config` and optionally `color`, `head`, and `tail`.
+    #[clap(long, value_parser)]
+    snakes: Option<PathBuf>,
+    /// Reload the snakes when the `--snakes` file changes.
+    ///
+    /// Running games keep their agent, and removed snakes are still served.
+    #[clap(long, requires = "snakes", value_parser)]
+    watch: bool,
+    /// Bearer token that enables the admin API at `/admin/config[/<name>]`.
+    #[clap(long, value_parser)]
+    admin_token: Option<String>,
 }
 
 #[tokio::main]
@@ -55,74 +79,52 @@ async fn main() {
     let Opt {
         host,
         latency,
+        adaptive_latency,
+        latency_margin,
+        latency_floor,
         color,
         head,
         tail,
         config,
+        ponder,
+        session_ttl,
+        record_dir,
+        snakes,
+        watch,
+        admin_token,
     } = Opt::parse();
 
-    let state = Arc::new(State {
-        latency,
+    let default = Snake {
         color,
         head,
         tail,
-        config,
+        config: Arc::new(config),
+    };
+    let state = Arc::new(State {
+        latency: LatencyConfig {
+            adaptive: adaptive_latency,
+            fallback: latency,
+            margin: latency_margin,
+            floor: latency_floor,
+        },
+        snakes: RwLock::new(HashMap::from([(String::new(), Arc::new(default))])),
+        sessions: SessionStore::new(Duration::from_secs(session_ttl), ponder),
+        recorder: record_dir.map(Recorder::new),
+        admin_token,
     });
 
-    let index = warp::get()
-        .and(warp::path::end())
-        .and(with_state(state.clone()))
-        .map(|state: Arc<State>| {
-            warn!("index");
-            warp::reply::json(&IndexResponse::new(
-                API_VERSION.into(),
-                AUTHOR.into(),
-                state.color.clone().into(),
-                state.head.clone().into(),
-                state.tail.clone().into(),
-                PACKAGE_VERSION.into(),
-            ))
-        });
-
-    let start = warp::path("start")
-        .and(warp::post())
-        .and(warp::body::json::<GameRequest>())
-        .map(|request: GameRequest| {
-            warn!("start {request}");
-            warp::reply()
-        });
-
-    let r#move = warp::path("move")
-        .and(warp::post())
-        .and(warp::body::json::<GameRequest>())
-        .and(with_state(state.clone()))
-        .and_then(step);
-
-    let end = warp::path("end")
-        .and(warp::post())
-        .and(warp::body::json::<GameRequest>())
-        .map(|request: GameRequest| {
-            warn!("end {request}");
-            warp::reply()
-        });
-
-    warp::serve(index.or(start).or(r#move).or(end))
-        .run(host)
-        .await;
-}
-
-fn with_state(
-    config: Arc<State>,
-) -> impl Filter<Extract = (Arc<State>,), Error = std::convert::Infallible> + Clone {
-    warp::any().map(move || config.clone())
-}
-
-async fn step(request: GameRequest, state: Arc<State>) -> Result<impl warp::Reply, Infallible> {
-    warn!("move {request}");
-
-    let timer = Instant::now();
-    let next_move = state.config.step(&request, state.latency).await;
-    info!("response time {:?}ms", timer.elapsed().as_millis());
-
-    Ok(warp::reply::json(&next_move))
+    if let Some(path) = snakes {
+        match load_snakes(&path) {
+            Ok(configs) => state.reload(configs),
+            Err(e) => {
+                eprintln!("Invalid snakes {path:?}: {e}");
+                std::process::exit(1);
+            }
+        }
+        if watch {
+            tokio::spawn(watch_snakes(path, state.clone()));
+        }
+    }
+
+    warp::serve(server::routes(state)).run(host).await;
 }
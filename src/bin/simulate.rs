@@ -2,15 +2,16 @@ use clap::Parser;
 use log::{debug, info, warn};
 use owo_colors::OwoColorize;
 
-use snork::agents::Agent;
+use snork::agents::{Agent, Setup};
 use snork::env::*;
-use snork::game::{Game, Outcome, Snake};
-use snork::grid::CellT;
+use snork::food::{food_spawner, FoodSpawner};
+use snork::game::{Game, Mode, Outcome, Snake};
 use snork::logging;
+use snork::rules::Map;
 
 use rand::prelude::*;
 use rand::seq::IteratorRandom;
-use std::iter::repeat;
+use std::iter::repeat_n;
 use std::time::Instant;
 
 #[derive(clap::Parser)]
@@ -19,18 +20,33 @@ struct Opts {
     /// Time each snake has for a turn.
     #[clap(long, default_value_t = 200, value_parser)]
     timeout: u64,
+    /// Name of the ruleset (e.g. standard, royale, wrapped, constrictor).
+    #[clap(long, default_value = "standard", value_parser)]
+    ruleset: String,
+    /// JSON ruleset settings, as sent by the game server
+    /// (e.g. '{"foodSpawnChance":25,"royale":{"shrinkEveryNTurns":20}}').
+    /// Defaults to the settings of the map (the arcade_maze has deadly hazards).
+    #[clap(long, value_parser = parse_settings)]
+    settings: Option<RulesetSettings>,
+    /// Chance new food spawns (overrides the `foodSpawnChance` of the settings).
+    #[clap(long, value_parser)]
+    food_rate: Option<f64>,
+    /// Number of turns after which the hazard expands
+    /// (overrides the `shrinkEveryNTurns` of the settings).
+    #[clap(short, long, value_parser)]
+    shrink_turns: Option<usize>,
+    /// Name of the map that places the hazards and food
+    /// (e.g. standard, royale, hz_inner_wall, hz_rings, hz_spiral, hz_scatter, arcade_maze).
+    /// Defaults to the map of the ruleset.
+    /// Maps with a fixed size override the board size.
+    #[clap(long, value_parser)]
+    map: Option<String>,
     /// Board height.
     #[clap(long, default_value_t = 11, value_parser)]
     width: usize,
     /// Board width.
     #[clap(long, default_value_t = 11, value_parser)]
     height: usize,
-    /// Chance new food spawns.
-    #[clap(long, default_value_t = 0.15, value_parser)]
-    food_rate: f64,
-    /// Number of turns after which the hazard expands.
-    #[clap(short, long, default_value_t = 25, value_parser)]
-    shrink_turns: usize,
     /// Number of games that are played.
     #[clap(short, long, default_value_t = 1, value_parser)]
     game_count: usize,
@@ -52,16 +68,23 @@ fn parse_request(s: &str) -> Result<GameRequest, serde_json::Error> {
     serde_json::from_str(s)
 }
 
+fn parse_settings(s: &str) -> Result<RulesetSettings, serde_json::Error> {
+    serde_json::from_str(s)
+}
+
 #[tokio::main]
 async fn main() {
     logging();
 
     let Opts {
         timeout,
-        width,
-        height,
+        ruleset,
+        settings,
         food_rate,
         shrink_turns,
+        map,
+        width,
+        height,
         game_count,
         swap,
         seed,
@@ -69,12 +92,21 @@ async fn main() {
         mut agents,
     } = Opts::parse();
 
-    assert!(agents.len() <= 4, "Only up to 4 snakes are supported");
+    assert!(agents.len() <= 8, "Only up to 8 snakes are supported");
+
+    let mut settings =
+        settings.unwrap_or_else(|| Map::from(map.as_deref().unwrap_or(&ruleset)).settings());
+    if let Some(food_rate) = food_rate {
+        settings.food_spawn_chance = (food_rate * 100.0).round().clamp(0.0, 100.0) as u8;
+    }
+    if let Some(shrink_turns) = shrink_turns {
+        settings.royale.shrink_every_n_turns = shrink_turns;
+    }
     info!("agents: {agents:?}");
 
     let start = Instant::now();
 
-    let mut wins = repeat(0).take(agents.len()).collect::<Vec<usize>>();
+    let mut wins = repeat_n(0, agents.len()).collect::<Vec<usize>>();
 
     for _ in 0..agents.len() {
         let mut rng = if seed == 0 {
@@ -84,18 +116,54 @@ async fn main() {
         };
 
         for i in 0..game_count {
-            let mut game = if let Some(request) = &init {
-                Game::from_request(request)
+            // Seed of the map hazards
+            let seed = rng.gen();
+
+            let (mut game, ruleset, map_name) = if let Some(request) = &init {
+                let map = match &map {
+                    Some(map) => map,
+                    None if request.game.map.is_empty() => &request.game.ruleset.name,
+                    None => &request.game.map,
+                };
+                (
+                    Game::from_request(request),
+                    request.game.ruleset.name.as_str(),
+                    map.as_str(),
+                )
             } else {
-                init_game(width, height, agents.len(), &mut rng)
+                let mode = Mode::from(ruleset.as_str());
+                let map_name = map.as_deref().unwrap_or(&ruleset);
+                let map = Map::from(map_name);
+                let (width, height) = map.size().unwrap_or((width, height));
+                let mut game = init_game(mode, width, height, agents.len(), &mut rng);
+                game.set_settings(settings);
+                map.update(&mut game.grid, game.turn, &game.settings, seed);
+                food_spawner(&game, map).init(&mut game, &mut rng);
+                (game, ruleset.as_str(), map_name)
             };
+            let map = Map::from(map_name);
+
+            // Select the agents of `Auto` configs for the whole game
+            let setup = Setup {
+                ruleset,
+                map: map_name,
+                snakes: Some(game.snakes.len()),
+                width: game.grid.width,
+                height: game.grid.height,
+            };
+            let selected = agents
+                .iter()
+                .map(|agent| agent.select(&setup).clone())
+                .collect::<Vec<_>>();
 
+            let food = food_spawner(&game, map);
             let outcome = play_game(
-                &agents,
+                &selected,
                 &mut game,
                 timeout,
-                food_rate,
-                shrink_turns,
+                map,
+                seed,
+                food.as_ref(),
                 &mut rng,
             )
             .await;
@@ -124,18 +192,15 @@ async fn play_game(
     agents: &[Agent],
     game: &mut Game,
     timeout: u64,
-    food_rate: f64,
-    shrink_turns: usize,
+    map: Map,
+    seed: u64,
+    food: &dyn FoodSpawner,
     rng: &mut SmallRng,
 ) -> Outcome {
-    let mut food_count = 4;
-
     debug!("init: {game:?}");
 
-    let mut hazard_insets = [0; 4];
-
     for turn in game.turn.. {
-        let mut moves = [Direction::Up; 4];
+        let mut moves = vec![Direction::Up; game.snakes.len()];
         for i in 0..game.snakes.len() {
             if game.snakes[i].alive() {
                 // Agents assume player 0 is you.
@@ -159,117 +224,72 @@ async fn play_game(
             return outcome;
         }
 
-        // Check if snakes have consumed food
-        for snake in &game.snakes {
-            if snake.alive() && snake.health == 100 {
-                food_count -= 1;
-            }
-        }
-
-        // Spawn food
-        if food_count == 0 || rng.gen::<f64>() < food_rate {
-            if let Some(cell) = game
-                .grid
-                .cells
-                .iter_mut()
-                .filter(|c| c.t == CellT::Free)
-                .choose(rng)
-            {
-                cell.t = CellT::Food;
-                food_count += 1;
-            }
-        }
+        food.spawn(game, rng);
 
         // Hazards
-        if turn > 0
-            && turn % shrink_turns == 0
-            && hazard_insets[0] + hazard_insets[2] < game.grid.height
-            && hazard_insets[1] + hazard_insets[3] < game.grid.width
-        {
-            let dir = rng.gen_range(0..4);
-            hazard_insets[dir] += 1;
-            if dir % 2 == 0 {
-                let y = if dir == 0 {
-                    hazard_insets[dir] - 1
-                } else {
-                    game.grid.height - hazard_insets[dir]
-                };
-                for x in 0..game.grid.width {
-                    game.grid[v2(x as _, y as _)].hazard = true;
-                }
-            } else {
-                let x = if dir == 1 {
-                    hazard_insets[dir] - 1
-                } else {
-                    game.grid.width - hazard_insets[dir]
-                };
-                for y in 0..game.grid.height {
-                    game.grid[v2(x as _, y as _)].hazard = true;
-                }
-            }
-        }
+        let settings = game.settings;
+        map.update(&mut game.grid, game.turn, &settings, seed);
     }
     Outcome::Match
 }
 
-fn init_game(width: usize, height: usize, num_agents: usize, rng: &mut SmallRng) -> Game {
-    if width % 2 == 0 || height % 2 == 0 {
+fn init_game(
+    mode: Mode,
+    width: usize,
+    height: usize,
+    num_agents: usize,
+    rng: &mut SmallRng,
+) -> Game {
+    if width.is_multiple_of(2) || height.is_multiple_of(2) {
         warn!("If the dimension are even, the initial board configuration is unfair!");
     }
     if width != height {
         warn!("If width != height, the initial board configuration is unfair!");
     }
 
-    // Either start in the corners or in the middle of the edges
-    let mut start_positions = if rng.gen() {
-        // Corners
-        [
-            v2(1, 1),
-            v2((width - 2) as _, 1),
-            v2((width - 2) as _, (height - 2) as _),
-            v2(1, (height - 2) as _),
-        ]
+    let corners = [
+        v2(1, 1),
+        v2((width - 2) as _, 1),
+        v2((width - 2) as _, (height - 2) as _),
+        v2(1, (height - 2) as _),
+    ];
+    let edges = [
+        v2((width / 2) as _, 1),
+        v2((width - 2) as _, (height / 2) as _),
+        v2((width / 2) as _, (height - 2) as _),
+        v2(1, (height / 2) as _),
+    ];
+
+    // Either start in the corners or in the middle of the edges,
+    // more than four snakes also occupy the other positions
+    let (first, second) = if rng.gen() {
+        (corners, edges)
     } else {
-        // Edges
-        [
-            v2((width / 2) as _, 1),
-            v2((width - 2) as _, (height / 2) as _),
-            v2((width / 2) as _, (height - 2) as _),
-            v2(1, (height / 2) as _),
-        ]
+        (edges, corners)
+    };
+    let mut start_positions = first.into_iter().choose_multiple(rng, num_agents);
+    if num_agents > first.len() {
+        start_positions.extend(
+            second
+                .into_iter()
+                .choose_multiple(rng, num_agents - first.len()),
+        );
     }
-    .into_iter()
-    .choose_multiple(rng, num_agents);
 
     start_positions.shuffle(rng);
 
     let snakes = start_positions
         .into_iter()
-        .map(|p| Snake::new(vec![p; 3].into(), 100))
+        .enumerate()
+        .map(|(i, p)| {
+            let mut snake = Snake::new(vec![p; 3].into(), 100);
+            // Squads of two consecutive agents
+            if mode == Mode::Squad {
+                snake.squad = Some(i as u8 / 2);
+            }
+            snake
+        })
         .collect();
 
-    let mut game = Game::new(0, width, height, snakes, &[], &[]);
-
-    // Food at center
-    game.grid[(width / 2, height / 2).into()].t = CellT::Food;
-
-    // Spawn 1 food 2 steps away from each snake
-    for snake in game.snakes.clone() {
-        let p = [v2(-1, -1), v2(-1, 1), v2(1, 1), v2(1, -1)]
-            .into_iter()
-            .map(|p| snake.head() + p)
-            // Only free cells on the board
-            .filter(|&p| game.grid.has(p) && game.grid[p].t != CellT::Owned)
-            // Limit to a border cells (excluding the corners)
-            .filter(|&p| {
-                (p.x == 0 || p.x == game.grid.width as i16 - 1)
-                    ^ (p.y == 0 || p.y == game.grid.height as i16 - 1)
-            })
-            .choose(rng);
-        if let Some(p) = p {
-            game.grid[p].t = CellT::Food;
-        }
-    }
-
-    game
+    Game::new(0, mode, width, height, snakes, &[], &[])
 }
@@ -0,0 +1,543 @@
+use std::cell::Cell;
+use std::ops::{BitAnd, BitAndAssign, BitOr, BitOrAssign, Not, Shl, Shr};
+
+use crate::agents::MAX_BOARD_SIZE;
+use crate::env::Vec2D;
+use crate::floodfill::MAX_FOOD_DISTANCES;
+use crate::game::{Game, Mode};
+use crate::grid::CellT;
+use crate::util::FixedVec;
+
+/// Enough words for the largest boards that are played by the agents.
+const WORDS: usize = (MAX_BOARD_SIZE * MAX_BOARD_SIZE).div_ceil(128);
+
+/// Maximum number of cells that fit into a bitboard (enough for 21x21 boards).
+pub const MAX_CELLS: usize = WORDS * 128;
+
+const _: () = assert!(MAX_CELLS >= MAX_BOARD_SIZE * MAX_BOARD_SIZE);
+
+/// Set of cells with a single bit per cell.
+///
+/// The cells are indexed like the grid (`x + y * width`).
+#[derive(Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub struct BitBoard([u128; WORDS]);
+
+impl BitBoard {
+    pub const EMPTY: Self = Self([0; WORDS]);
+
+    /// Returns a bitboard with the first `n` bits set.
+    pub fn filled(n: usize) -> Self {
+        let mut board = Self::EMPTY;
+        for (i, word) in board.0.iter_mut().enumerate() {
+            let bits = n.saturating_sub(i * 128).min(128);
+            *word = if bits == 128 {
+                u128::MAX
+            } else {
+                (1 << bits) - 1
+            };
+        }
+        board
+    }
+
+    pub fn get(&self, i: usize) -> bool {
+        self.0[i / 128] >> (i % 128) & 1 != 0
+    }
+
+    pub fn set(&mut self, i: usize) {
+        self.0[i / 128] |= 1 << (i % 128);
+    }
+
+    pub fn unset(&mut self, i: usize) {
+        self.0[i / 128] &= !(1 << (i % 128));
+    }
+
+    /// Number of set bits.
+    pub fn count(&self) -> usize {
+        self.0.iter().map(|w| w.count_ones() as usize).sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.iter().all(|&w| w == 0)
+    }
+
+    /// Iterates over the indices of the set bits.
+    pub fn iter(&self) -> impl Iterator<Item = usize> + '_ {
+        (0..MAX_CELLS).filter(|&i| self.get(i))
+    }
+}
+
+/// Moves all bits `n` positions towards the higher indices.
+impl Shl<usize> for BitBoard {
+    type Output = Self;
+
+    fn shl(self, n: usize) -> Self {
+        // Fast path for the shifts by one row or column
+        if 0 < n && n < 128 {
+            let mut result = self;
+            for i in (1..WORDS).rev() {
+                result.0[i] = self.0[i] << n | self.0[i - 1] >> (128 - n);
+            }
+            result.0[0] = self.0[0] << n;
+            return result;
+        }
+        let (words, bits) = (n / 128, n % 128);
+        let mut result = Self::EMPTY;
+        for i in words..WORDS {
+            let src = i - words;
+            result.0[i] = self.0[src] << bits;
+            if bits > 0 && src > 0 {
+                result.0[i] |= self.0[src - 1] >> (128 - bits);
+            }
+        }
+        result
+    }
+}
+
+/// Moves all bits `n` positions towards the lower indices.
+impl Shr<usize> for BitBoard {
+    type Output = Self;
+
+    fn shr(self, n: usize) -> Self {
+        if 0 < n && n < 128 {
+            let mut result = self;
+            for i in 0..WORDS - 1 {
+                result.0[i] = self.0[i] >> n | self.0[i + 1] << (128 - n);
+            }
+            result.0[WORDS - 1] = self.0[WORDS - 1] >> n;
+            return result;
+        }
+        let (words, bits) = (n / 128, n % 128);
+        let mut result = Self::EMPTY;
+        for i in 0..WORDS.saturating_sub(words) {
+            let src = i + words;
+            result.0[i] = self.0[src] >> bits;
+            if bits > 0 && src + 1 < WORDS {
+                result.0[i] |= self.0[src + 1] << (128 - bits);
+            }
+        }
+        result
+    }
+}
+
+impl BitAnd for BitBoard {
+    type Output = Self;
+
+    fn bitand(mut self, rhs: Self) -> Self {
+        self &= rhs;
+        self
+    }
+}
+
+impl BitAndAssign for BitBoard {
+    fn bitand_assign(&mut self, rhs: Self) {
+        for (a, b) in self.0.iter_mut().zip(rhs.0) {
+            *a &= b;
+        }
+    }
+}
+
+impl BitOr for BitBoard {
+    type Output = Self;
+
+    fn bitor(mut self, rhs: Self) -> Self {
+        self |= rhs;
+        self
+    }
+}
+
+impl BitOrAssign for BitBoard {
+    fn bitor_assign(&mut self, rhs: Self) {
+        for (a, b) in self.0.iter_mut().zip(rhs.0) {
+            *a |= b;
+        }
+    }
+}
+
+impl Not for BitBoard {
+    type Output = Self;
+
+    fn not(self) -> Self {
+        Self(self.0.map(|w| !w))
+    }
+}
+
+impl std::fmt::Debug for BitBoard {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_set().entries(self.iter()).finish()
+    }
+}
+
+/// Flood fill on bitboards that computes the area control of the snakes.
+///
+/// This is a faster alternative to the [`crate::floodfill::FloodFill`].
+/// All snakes expand their frontiers simultaneously by shifting bitboards.
+/// Cells that are reached at the same time are owned by the longer snake
+/// (on a draw by the higher id), and snake bodies become passable
+/// as soon as their tails have moved away.
+/// Snakes stop expanding when they would starve.
+///
+/// Unlike the `FloodFill` food (growth and restored health) and hazard damage
+/// are not accounted for.
+/// Without them, both compute the same area (see [`BitFloodFill::is_exact`]).
+pub struct BitFloodFill {
+    owned: Vec<BitBoard>,
+    /// Total remaining health on the owned cells of the snakes
+    health: Vec<usize>,
+    /// All cells of the board
+    board: BitBoard,
+    /// Cells of the first column
+    left: BitBoard,
+    /// Cells of the last column
+    right: BitBoard,
+    /// Cells that become passable after the distance of their index
+    releases: Vec<BitBoard>,
+    /// Buffer for the id, length, health and frontier of the snakes
+    fronts: Vec<(usize, usize, usize, BitBoard)>,
+    pub width: usize,
+    pub height: usize,
+}
+
+thread_local! {
+    static CACHED: Cell<Option<BitFloodFill>> = const { Cell::new(None) };
+}
+
+impl BitFloodFill {
+    /// Returns if the board can be flooded with bitboards,
+    /// and if the result is the same as the one of the `FloodFill`,
+    /// which is the case if there is no food and there are no hazards.
+    pub fn is_exact(game: &Game) -> bool {
+        let grid = &game.grid;
+        grid.width * grid.height <= MAX_CELLS
+            && grid
+                .cells
+                .iter()
+                .all(|c| c.t != CellT::Food && c.hazards == 0)
+    }
+
+    #[must_use]
+    pub fn new(width: usize, height: usize) -> Self {
+        assert!(width > 0 && width * height <= MAX_CELLS, "board too large");
+        let mut left = BitBoard::EMPTY;
+        let mut right = BitBoard::EMPTY;
+        for y in 0..height {
+            left.set(y * width);
+            right.set(y * width + width - 1);
+        }
+        Self {
+            owned: Vec::new(),
+            health: Vec::new(),
+            board: BitBoard::filled(width * height),
+            left,
+            right,
+            releases: Vec::new(),
+            fronts: Vec::new(),
+            width,
+            height,
+        }
+    }
+
+    /// Calls `f` with a flood fill of the given size,
+    /// whose buffers are reused by the following calls on the same thread.
+    pub fn with_cached<R>(width: usize, height: usize, f: impl FnOnce(&mut Self) -> R) -> R {
+        let mut flood_fill = CACHED
+            .take()
+            .filter(|f| f.width == width && f.height == height)
+            .unwrap_or_else(|| Self::new(width, height));
+        let result = f(&mut flood_fill);
+        CACHED.set(Some(flood_fill));
+        result
+    }
+
+    fn index(&self, p: Vec2D) -> usize {
+        assert!(0 <= p.x && p.x < self.width as _);
+        assert!(0 <= p.y && p.y < self.height as _);
+        p.x as usize + p.y as usize * self.width
+    }
+
+    /// Cells that are adjacent to the given cells.
+    fn neighbors(&self, cells: BitBoard, wrapped: bool) -> BitBoard {
+        let width = self.width;
+        let mut result = (cells & !self.right) << 1
+            | (cells & !self.left) >> 1
+            | cells << width
+            | cells >> width;
+        if wrapped {
+            let last_row = width * (self.height - 1);
+            result |= (cells & self.right) >> (width - 1)
+                | (cells & self.left) << (width - 1)
+                | cells >> last_row
+                | cells << last_row;
+        }
+        result & self.board
+    }
+
+    /// Counts the space of you or the enemies.
+    pub fn count_space(&self, id: u8) -> usize {
+        self.owned.get(id as usize).map_or(0, BitBoard::count)
+    }
+
+    /// Counts the total health of you or the enemies.
+    pub fn count_health(&self, id: u8) -> usize {
+        self.health.get(id as usize).copied().unwrap_or_default()
+    }
+
+    /// Returns the cells owned by the snake.
+    pub fn owned(&self, id: u8) -> BitBoard {
+        self.owned.get(id as usize).copied().unwrap_or_default()
+    }
+
+    /// Returns the snake that owns the cell.
+    pub fn owner(&self, p: Vec2D) -> Option<u8> {
+        let i = self.index(p);
+        self.owned.iter().position(|o| o.get(i)).map(|id| id as u8)
+    }
+
+    /// Computes the area control of all snakes.
+    /// Returns the distances to the food cells that are owned by you, like the `FloodFill`.
+    ///
+    /// Obstacles on the grid that do not belong to one of the simulated snakes
+    /// (like frozen snakes) are never passable.
+    pub fn flood_snakes(&mut self, game: &Game) -> FixedVec<u16, MAX_FOOD_DISTANCES> {
+        let grid = &game.grid;
+        debug_assert!(grid.width == self.width && grid.height == self.height);
+
+        let mut blocked = BitBoard::EMPTY;
+        let mut food = BitBoard::EMPTY;
+        for (i, cell) in grid.cells.iter().enumerate() {
+            match cell.t {
+                CellT::Owned => blocked.set(i),
+                CellT::Food => food.set(i),
+                CellT::Free => {}
+            }
+        }
+
+        // Cells that become passable after the given distance (tail = 0, ..., head = n - 1)
+        // In constrictor games tails do not move and bodies remain obstacles
+        self.releases.clear();
+        if game.mode != Mode::Constrictor {
+            // Stacked segments are released with the segment that is closest to the head
+            let mut seen = BitBoard::EMPTY;
+            for snake in game.snakes.iter().rev() {
+                for (i, &p) in snake.body.iter().enumerate().rev() {
+                    let idx = self.index(p);
+                    if seen.get(idx) {
+                        continue;
+                    }
+                    seen.set(idx);
+                    if i == 0 {
+                        blocked.unset(idx);
+                    } else {
+                        blocked.set(idx);
+                        if self.releases.len() <= i {
+                            self.releases.resize(i + 1, BitBoard::EMPTY);
+                        }
+                        self.releases[i].set(idx);
+                    }
+                }
+            }
+        }
+
+        // Longer snakes (on a draw the higher id) win contested cells
+        let mut fronts = std::mem::take(&mut self.fronts);
+        fronts.clear();
+        fronts.extend(
+            game.snakes
+                .iter()
+                .enumerate()
+                .filter(|(_, s)| s.alive())
+                .map(|(id, s)| {
+                    let mut frontier = BitBoard::EMPTY;
+                    frontier.set(self.index(s.head()));
+                    (id, s.body.len(), s.health as usize, frontier)
+                }),
+        );
+        fronts.sort_unstable_by_key(|&(id, len, ..)| std::cmp::Reverse((len, id)));
+
+        self.owned.clear();
+        self.owned.resize(game.snakes.len(), BitBoard::EMPTY);
+        self.health.clear();
+        self.health.resize(game.snakes.len(), 0);
+        let mut food_distances = FixedVec::new();
+
+        let wrapped = grid.wrapped;
+        let mut reached = BitBoard::EMPTY;
+        for distance in 0.. {
+            if let Some(&released) = self.releases.get(distance) {
+                blocked &= !released;
+            }
+
+            let mut active = false;
+            for (id, _, health, frontier) in &mut fronts {
+                // Every move costs one health
+                if frontier.is_empty() || distance + 1 >= *health {
+                    *frontier = BitBoard::EMPTY;
+                    continue;
+                }
+                let cells = self.neighbors(*frontier, wrapped) & !blocked & !reached;
+                reached |= cells;
+                self.owned[*id] |= cells;
+                self.health[*id] += cells.count() * (*health - distance - 1);
+                if *id == 0 {
+                    for _ in 0..(cells & food).count() {
+                        food_distances.push(distance as u16);
+                    }
+                }
+                *frontier = cells;
+                active |= !cells.is_empty();
+            }
+            if !active {
+                break;
+            }
+        }
+        self.fronts = fronts;
+        food_distances
+    }
+}
+
+impl std::fmt::Debug for BitFloodFill {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "BitFloodFill {{")?;
+        for y in (0..self.height as i16).rev() {
+            write!(f, "  ")?;
+            for x in 0..self.width as i16 {
+                match self.owner(Vec2D::new(x, y)) {
+                    Some(id) => write!(f, "{id} ")?,
+                    None => write!(f, ". ")?,
+                }
+            }
+            writeln!(f)?;
+        }
+        write!(f, "}}")?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use log::info;
+
+    use crate::logging;
+
+    #[test]
+    fn bitboard_shifts() {
+        use super::*;
+        logging();
+
+        let mut board = BitBoard::EMPTY;
+        board.set(0);
+        board.set(127);
+        board.set(200);
+        assert_eq!(board.count(), 3);
+
+        let shifted = board << 130;
+        assert_eq!(shifted.iter().collect::<Vec<_>>(), vec![130, 257, 330]);
+        assert_eq!(shifted >> 130, board);
+        // Bits are shifted out on both ends
+        assert_eq!((board << 400).iter().collect::<Vec<_>>(), vec![400]);
+        assert_eq!((board >> 128).iter().collect::<Vec<_>>(), vec![72]);
+
+        assert_eq!(BitBoard::filled(MAX_CELLS), !BitBoard::EMPTY);
+        assert_eq!(BitBoard::filled(19 * 19).count(), 19 * 19);
+        // The arcade maze fits
+        let mut board = BitBoard::EMPTY;
+        board.set(19 * 21 - 1);
+        assert_eq!((board >> (19 * 20)).iter().collect::<Vec<_>>(), vec![18]);
+        assert_eq!((board >> 1 << 1), board);
+    }
+
+    #[test]
+    fn bit_flood_enemy() {
+        use super::*;
+        logging();
+
+        let game = Game::parse(
+            r#"
+            . . . . . . . . . . .
+            . . . . . . . . . . .
+            . . . . . . . . . . .
+            . . . . . . . . . . .
+            . . . . . . . . . . .
+            . . . . . . . . . . .
+            . . . . . . . . . . .
+            1 . . . . . . . . . .
+            ^ v . . . . . . . . .
+            ^ > v . . . . . . . .
+            . 0 < . . . . . . . ."#,
+        )
+        .unwrap();
+
+        let mut flood_fill = BitFloodFill::new(game.grid.width, game.grid.height);
+        flood_fill.flood_snakes(&game);
+        info!("{flood_fill:?}");
+        assert_eq!(flood_fill.count_space(0), 24);
+        assert_eq!(flood_fill.owner(Vec2D::new(0, 0)), Some(0));
+        assert!(BitFloodFill::is_exact(&game));
+
+        // Food and hazards are ignored
+        let mut game = game.clone();
+        game.grid[Vec2D::new(5, 5)].t = CellT::Food;
+        assert!(!BitFloodFill::is_exact(&game));
+        game.grid[Vec2D::new(5, 5)].t = CellT::Free;
+        game.grid[Vec2D::new(5, 5)].hazards = 1;
+        assert!(!BitFloodFill::is_exact(&game));
+    }
+
+    /// Compares the bitboard with the default flood fill on random games
+    /// without food and hazards.
+    #[test]
+    fn bit_flood_equivalence() {
+        use super::*;
+        use crate::env::Direction;
+        use crate::floodfill::FloodFill;
+        use crate::game::{Outcome, Snake};
+        use rand::{rngs::SmallRng, seq::IteratorRandom, Rng, SeedableRng};
+        logging();
+
+        let mut rng = SmallRng::seed_from_u64(42);
+        for mode in [Mode::Standard, Mode::Wrapped, Mode::Constrictor] {
+            for (width, height) in [(11, 11), (7, 19), (19, 19), (19, 21)] {
+                for _ in 0..10 {
+                    // Long snakes in the corners
+                    let health = rng.gen_range(10..=100);
+                    let (w, h) = (width as i16 - 1, height as i16 - 1);
+                    let snakes = [(0, 0, 1), (w, h, -1), (0, h, -1), (w, 0, 1)]
+                        .into_iter()
+                        .map(|(x, y, dy)| {
+                            let len = rng.gen_range(2..=6);
+                            let body = (0..len).rev().map(|i| Vec2D::new(x, y + i * dy));
+                            Snake::new(body.collect(), health)
+                        })
+                        .collect();
+                    let mut game = Game::new(0, mode, width, height, snakes, &[], &[]);
+
+                    while game.outcome() == Outcome::None {
+                        assert!(BitFloodFill::is_exact(&game));
+                        let mut expected = FloodFill::new(width, height);
+                        expected.flood_snakes(&game);
+                        // Reused buffers lead to the same results
+                        BitFloodFill::with_cached(width, height, |flood_fill| {
+                            flood_fill.flood_snakes(&game);
+                            for id in 0..game.snakes.len() as u8 {
+                                assert_eq!(
+                                    flood_fill.count_space(id),
+                                    expected.count_space(id),
+                                    "{game:?} {expected:?} {flood_fill:?}"
+                                );
+                                assert_eq!(flood_fill.count_health(id), expected.count_health(id));
+                            }
+                        });
+
+                        let moves = (0..game.snakes.len() as u8)
+                            .map(|i| {
+                                game.valid_moves(i)
+                                    .choose(&mut rng)
+                                    .unwrap_or(Direction::Up)
+                            })
+                            .collect::<Vec<_>>();
+                        game.step(&moves);
+                    }
+                }
+            }
+        }
+    }
+}
@@ -12,7 +12,10 @@ use std::ops::{Add, Neg, Sub};
 
 pub const API_VERSION: &str = "1";
 
-pub const HAZARD_DAMAGE: u8 = 15;
+/// Default `hazardDamagePerTurn` if the ruleset does not specify it.
+///
+/// Snakes on a hazard lose this in addition to the normal health loss of 1.
+pub const HAZARD_DAMAGE: u8 = 14;
 
 /// Position in the a 2D grid.
 #[derive(Serialize, Deserialize, Default, Clone, Copy, Debug, PartialEq, Eq, Hash)]
@@ -107,11 +110,12 @@ impl Neg for Vec2D {
 /// The Direction is returned as part of a `MoveResponse`.
 ///
 /// The Y-Axis is positive in the up direction, and X-Axis is positive to the right.
-#[derive(Serialize, Debug, Clone, Copy, Hash, PartialEq, Eq)]
+#[derive(Serialize, Deserialize, Debug, Default, Clone, Copy, Hash, PartialEq, Eq)]
 #[serde(rename_all = "lowercase")]
 #[repr(u8)]
 pub enum Direction {
     /// Positive Y
+    #[default]
     Up,
     /// Positive X
     Right,
@@ -139,12 +143,6 @@ impl Direction {
     }
 }
 
-impl Default for Direction {
-    fn default() -> Self {
-        Self::Up
-    }
-}
-
 impl From<Vec2D> for Direction {
     fn from(p: Vec2D) -> Self {
         if p.x < 0 {
@@ -179,6 +177,9 @@ pub struct GameData {
     /// Information about the ruleset being used to run this game.
     #[serde(default)]
     pub ruleset: Ruleset,
+    /// The name of the map used to populate the board (e.g. standard, royale, hz_spiral).
+    #[serde(default)]
+    pub map: String,
     /// How much time your snake has to respond to requests for this Game in milliseconds.
     pub timeout: u64,
     /// The source of this game. (tournament, league, arena, challenge, custom)
@@ -191,6 +192,79 @@ pub struct Ruleset {
     pub name: String,
     #[serde(default)]
     pub version: String,
+    /// Settings of the ruleset, missing values are set to their defaults.
+    #[serde(default)]
+    pub settings: RulesetSettings,
+}
+
+/// Settings that configure the ruleset.
+///
+/// See: https://docs.battlesnake.com/api/objects/ruleset-settings
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(default, rename_all = "camelCase")]
+pub struct RulesetSettings {
+    /// Percentage chance of spawning a new food every round.
+    pub food_spawn_chance: u8,
+    /// Minimum food to keep on the board every turn.
+    pub minimum_food: usize,
+    /// Health damage a snake will take when ending its turn in a hazard,
+    /// in addition to the normal health loss of 1.
+    pub hazard_damage_per_turn: u8,
+    pub royale: RoyaleSettings,
+    pub squad: SquadSettings,
+}
+
+impl Default for RulesetSettings {
+    fn default() -> Self {
+        Self {
+            food_spawn_chance: 15,
+            minimum_food: 1,
+            hazard_damage_per_turn: HAZARD_DAMAGE,
+            royale: RoyaleSettings::default(),
+            squad: SquadSettings::default(),
+        }
+    }
+}
+
+/// Settings that are specific to the royale ruleset.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(default, rename_all = "camelCase")]
+pub struct RoyaleSettings {
+    /// The number of turns between generating new hazards (shrinking the safe board space).
+    pub shrink_every_n_turns: usize,
+}
+
+impl Default for RoyaleSettings {
+    fn default() -> Self {
+        Self {
+            shrink_every_n_turns: 25,
+        }
+    }
+}
+
+/// Settings that are specific to the squad ruleset.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(default, rename_all = "camelCase")]
+pub struct SquadSettings {
+    /// Allow members of the same squad to move over each other without dying.
+    pub allow_body_collisions: bool,
+    /// All squad members are eliminated when one is eliminated.
+    pub shared_elimination: bool,
+    /// All squad members share health.
+    pub shared_health: bool,
+    /// All squad members share length.
+    pub shared_length: bool,
+}
+
+impl Default for SquadSettings {
+    fn default() -> Self {
+        Self {
+            allow_body_collisions: true,
+            shared_elimination: true,
+            shared_health: true,
+            shared_length: true,
+        }
+    }
 }
 
 /// Object describing a snake.
@@ -203,6 +277,28 @@ pub struct Battlesnake {
     pub body: Vec<Vec2D>,
     #[serde(default)]
     pub shout: String,
+    /// Name of the squad in squad games.
+    #[serde(default)]
+    pub squad: String,
+    /// Round trip time of the previous response in ms, as measured by the game engine.
+    ///
+    /// Empty in the first turn or if the previous response timed out.
+    #[serde(default, deserialize_with = "string_or_number")]
+    pub latency: String,
+}
+
+/// Older game engines send numbers instead of strings.
+fn string_or_number<'de, D: serde::Deserializer<'de>>(deserializer: D) -> Result<String, D::Error> {
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Value {
+        String(String),
+        Number(u64),
+    }
+    Ok(match Value::deserialize(deserializer)? {
+        Value::String(s) => s,
+        Value::Number(n) => n.to_string(),
+    })
 }
 
 impl PartialEq for Battlesnake {
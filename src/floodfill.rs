@@ -1,14 +1,77 @@
+use std::cell::Cell;
 use std::collections::VecDeque;
 use std::mem::size_of;
 use std::ops::{Index, IndexMut};
 
-use crate::env::{Direction, Vec2D, HAZARD_DAMAGE};
-use crate::game::Snake;
+use crate::bitboard::{BitFloodFill, MAX_CELLS};
+use crate::env::{Direction, Vec2D};
+use crate::game::{Game, Mode};
 use crate::grid::{CellT, Grid};
 use crate::util::FixedVec;
 
 use owo_colors::{AnsiColors, OwoColorize};
 
+/// Maximum number of food distances that are collected by the flood fill.
+pub const MAX_FOOD_DISTANCES: usize = 8;
+
+/// Flood fill implementation that is used by the heuristics.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, serde::Serialize, serde::Deserialize)]
+pub enum FloodBackend {
+    /// [`FloodFill`] that accounts for food and hazards.
+    #[default]
+    FloodFill,
+    /// Faster [`BitFloodFill`] that ignores food and hazards,
+    /// which falls back to the `FloodFill` on boards that do not fit.
+    BitBoard,
+}
+
+impl FloodBackend {
+    /// Floods the game with this backend and calls `f` with the area control
+    /// and the distances to the food that is owned by you.
+    ///
+    /// The buffers of the flood fills are reused like with `with_cached`.
+    pub fn flood<R>(self, game: &Game, f: impl FnOnce(&dyn AreaControl, &[u16]) -> R) -> R {
+        let (width, height) = (game.grid.width, game.grid.height);
+        if self == FloodBackend::BitBoard && width * height <= MAX_CELLS {
+            BitFloodFill::with_cached(width, height, |flood_fill| {
+                let food_distances = flood_fill.flood_snakes(game);
+                f(flood_fill, &food_distances)
+            })
+        } else {
+            FloodFill::with_cached(width, height, |flood_fill| {
+                let food_distances = flood_fill.flood_snakes(game);
+                f(flood_fill, &food_distances)
+            })
+        }
+    }
+}
+
+/// Area control of the snakes, as computed by one of the flood fills.
+pub trait AreaControl {
+    /// Counts the space of you or the enemies.
+    fn count_space(&self, id: u8) -> usize;
+    /// Counts the total health of you or the enemies.
+    fn count_health(&self, id: u8) -> usize;
+}
+
+impl AreaControl for FloodFill {
+    fn count_space(&self, id: u8) -> usize {
+        FloodFill::count_space(self, id)
+    }
+    fn count_health(&self, id: u8) -> usize {
+        FloodFill::count_health(self, id)
+    }
+}
+
+impl AreaControl for BitFloodFill {
+    fn count_space(&self, id: u8) -> usize {
+        BitFloodFill::count_space(self, id)
+    }
+    fn count_health(&self, id: u8) -> usize {
+        BitFloodFill::count_health(self, id)
+    }
+}
+
 /// Floodfill Cell that stores the important data in a single Byte.
 #[derive(Clone, Copy, PartialEq, Eq)]
 pub enum FCell {
@@ -82,20 +145,39 @@ impl SnakePos {
 /// This struct also contains all necessary buffers for the floodfill algorithm.
 pub struct FloodFill {
     cells: Vec<FCell>,
+    queue: VecDeque<SnakePos>,
     pub width: usize,
     pub height: usize,
 }
 
+thread_local! {
+    static CACHED: Cell<Option<FloodFill>> = const { Cell::new(None) };
+}
+
 impl FloodFill {
     #[must_use]
     pub fn new(width: usize, height: usize) -> Self {
         Self {
             cells: vec![FCell::Free; width * height],
+            // Assuming there are at most n^2 elements in the queue
+            queue: VecDeque::with_capacity(width * height),
             width,
             height,
         }
     }
 
+    /// Calls `f` with a flood fill of the given size,
+    /// whose buffers are reused by the following calls on the same thread.
+    pub fn with_cached<R>(width: usize, height: usize, f: impl FnOnce(&mut Self) -> R) -> R {
+        let mut flood_fill = CACHED
+            .take()
+            .filter(|f| f.width == width && f.height == height)
+            .unwrap_or_else(|| Self::new(width, height));
+        let result = f(&mut flood_fill);
+        CACHED.set(Some(flood_fill));
+        result
+    }
+
     /// Returns if `p` is within the boundaries of the board.
     pub fn has(&self, p: Vec2D) -> bool {
         0 <= p.x && p.x < self.width as _ && 0 <= p.y && p.y < self.height as _
@@ -156,7 +238,11 @@ impl FloodFill {
     /// This allows the snake to follow its tail or enemy tails.
     ///
     /// Food on the way is been accounted for the own tail.
-    fn flood(&mut self, grid: &Grid, heads: impl Iterator<Item = SnakePos>) -> FixedVec<u16, 4> {
+    fn flood(
+        &mut self,
+        grid: &Grid,
+        heads: impl Iterator<Item = SnakePos>,
+    ) -> FixedVec<u16, MAX_FOOD_DISTANCES> {
         #[inline]
         const fn owns(
             cell: FCell,
@@ -191,8 +277,8 @@ impl FloodFill {
             }
         }
 
-        // Assuming there are at most n^2 elements in the queue
-        let mut queue = VecDeque::with_capacity(self.width * self.height);
+        let mut queue = std::mem::take(&mut self.queue);
+        queue.clear();
         queue.extend(heads);
 
         // Collect food on the way
@@ -207,10 +293,7 @@ impl FloodFill {
             health,
         }) = queue.pop_front()
         {
-            for p in Direction::iter()
-                .map(|d| p.apply(d))
-                .filter(|&p| grid.has(p))
-            {
+            for p in Direction::iter().flat_map(|d| grid.neighbor(p, d)) {
                 let g_cell = grid[p];
                 let cell = self[p];
 
@@ -219,7 +302,7 @@ impl FloodFill {
                 let health = if is_food {
                     100
                 } else {
-                    health.saturating_sub(if g_cell.hazard { HAZARD_DAMAGE } else { 1 })
+                    health.saturating_sub(grid.damage(p))
                 };
 
                 // Collect food
@@ -241,21 +324,40 @@ impl FloodFill {
                 }
             }
         }
+        self.queue = queue;
         food_distances
     }
 
     /// Prepare the board and compute flood fill.
     /// It is assumed that the snake at position and id 0 is the evaluated
     /// agent and the other snakes are the enemies.
-    pub fn flood_snakes(&mut self, grid: &Grid, snakes: &[Snake]) -> FixedVec<u16, 4> {
-        self.clear();
+    ///
+    /// Obstacles on the grid that do not belong to one of the simulated snakes
+    /// (like frozen snakes) are never passable.
+    pub fn flood_snakes(&mut self, game: &Game) -> FixedVec<u16, MAX_FOOD_DISTANCES> {
+        let grid = &game.grid;
+        let snakes = &game.snakes;
+
+        for (cell, g_cell) in self.cells.iter_mut().zip(&grid.cells) {
+            *cell = if g_cell.t == CellT::Owned {
+                FCell::Occupied {
+                    id: u8::MAX,
+                    tail_dist: u16::MAX,
+                }
+            } else {
+                FCell::Free
+            };
+        }
 
         // Prepare board with snakes (tail = 1, ..., head = n)
-        for (id, snake) in snakes.iter().enumerate() {
-            for (i, p) in snake.body.iter().enumerate() {
-                self[*p] = FCell::Occupied {
-                    id: id as _,
-                    tail_dist: i as u16,
+        // In constrictor games tails do not move and bodies remain obstacles
+        if game.mode != Mode::Constrictor {
+            for (id, snake) in snakes.iter().enumerate() {
+                for (i, p) in snake.body.iter().enumerate() {
+                    self[*p] = FCell::Occupied {
+                        id: id as _,
+                        tail_dist: i as u16,
+                    }
                 }
             }
         }
@@ -278,7 +380,7 @@ impl Index<Vec2D> for FloodFill {
     fn index(&self, p: Vec2D) -> &Self::Output {
         assert!(0 <= p.x && p.x < self.width as _);
         assert!(0 <= p.y && p.y < self.height as _);
-        &self.cells[(p.x as usize % self.width + p.y as usize * self.width) as usize]
+        &self.cells[p.x as usize % self.width + p.y as usize * self.width]
     }
 }
 
@@ -286,7 +388,7 @@ impl IndexMut<Vec2D> for FloodFill {
     fn index_mut(&mut self, p: Vec2D) -> &mut Self::Output {
         assert!(0 <= p.x && p.x < self.width as _);
         assert!(0 <= p.y && p.y < self.height as _);
-        &mut self.cells[(p.x as usize % self.width + p.y as usize * self.width) as usize]
+        &mut self.cells[p.x as usize % self.width + p.y as usize * self.width]
     }
 }
 
@@ -354,6 +456,44 @@ mod test {
         assert_eq!(floodfill.count_space(0), 61);
     }
 
+    #[test]
+    fn flood_cached() {
+        use super::*;
+        use crate::game::Game;
+        logging();
+
+        let small = Game::parse(
+            r#"
+            . . . . .
+            . 0 . . .
+            . ^ . 1 .
+            . . . ^ .
+            . . . . ."#,
+        )
+        .unwrap();
+        let wide = Game::parse(
+            r#"
+            . . . . . . .
+            . 0 . . . . .
+            . ^ . . . 1 .
+            . . . . . ^ .
+            . . . . . . ."#,
+        )
+        .unwrap();
+
+        // The buffers are reused, and replaced for other board sizes
+        for game in [&small, &wide, &small] {
+            let mut expected = FloodFill::new(game.grid.width, game.grid.height);
+            expected.flood_snakes(game);
+            let space = FloodFill::with_cached(game.grid.width, game.grid.height, |flood_fill| {
+                assert_eq!(flood_fill.width, game.grid.width);
+                flood_fill.flood_snakes(game);
+                flood_fill.count_space(0)
+            });
+            assert_eq!(space, expected.count_space(0));
+        }
+    }
+
     #[test]
     fn flood_snakes_follow_tail() {
         use super::*;
@@ -377,7 +517,7 @@ mod test {
         .unwrap();
 
         let mut floodfill = FloodFill::new(game.grid.width, game.grid.height);
-        floodfill.flood_snakes(&game.grid, &game.snakes);
+        floodfill.flood_snakes(&game);
 
         info!("Filled {} {floodfill:?}", floodfill.count_space(0));
         assert_eq!(floodfill.count_space(0), 11 * 11);
@@ -399,7 +539,7 @@ mod test {
         .unwrap();
 
         let mut floodfill = FloodFill::new(game.grid.width, game.grid.height);
-        floodfill.flood_snakes(&game.grid, &game.snakes);
+        floodfill.flood_snakes(&game);
 
         info!("Filled {} {floodfill:?}", floodfill.count_space(0));
         assert_eq!(floodfill.count_space(0), 11 * 11);
@@ -428,7 +568,7 @@ mod test {
         .unwrap();
 
         let mut floodfill = FloodFill::new(game.grid.width, game.grid.height);
-        floodfill.flood_snakes(&game.grid, &game.snakes);
+        floodfill.flood_snakes(&game);
 
         info!("Filled {} {floodfill:?}", floodfill.count_space(0));
         assert_eq!(floodfill.count_space(0), 4);
@@ -457,7 +597,7 @@ mod test {
         .unwrap();
 
         let mut floodfill = FloodFill::new(game.grid.width, game.grid.height);
-        floodfill.flood_snakes(&game.grid, &game.snakes);
+        floodfill.flood_snakes(&game);
 
         info!("Filled {} {floodfill:?}", floodfill.count_space(0));
         assert_eq!(floodfill.count_space(0), 11 * 11);
@@ -479,7 +619,7 @@ mod test {
         .unwrap();
 
         floodfill.clear();
-        floodfill.flood_snakes(&game.grid, &game.snakes);
+        floodfill.flood_snakes(&game);
         info!("Filled {} {floodfill:?}", floodfill.count_space(0));
         assert_eq!(floodfill.count_space(0), 1);
     }
@@ -507,7 +647,7 @@ mod test {
         .unwrap();
 
         let mut floodfill = FloodFill::new(game.grid.width, game.grid.height);
-        floodfill.flood_snakes(&game.grid, &game.snakes);
+        floodfill.flood_snakes(&game);
         info!("Filled {} {floodfill:?}", floodfill.count_space(0));
         assert_eq!(floodfill.count_space(0), 24);
     }
@@ -536,7 +676,7 @@ mod test {
         game.snakes[0].health = 6;
 
         let mut floodfill = FloodFill::new(game.grid.width, game.grid.height);
-        floodfill.flood_snakes(&game.grid, &game.snakes);
+        floodfill.flood_snakes(&game);
         info!("Filled {} {floodfill:?}", floodfill.count_space(0));
         assert_eq!(floodfill.count_space(0), 59);
     }
@@ -565,12 +705,12 @@ mod test {
         game.snakes[0].health = 50;
         for y in 0..game.grid.height {
             for x in game.grid.width / 2 + 1..game.grid.width {
-                game.grid[Vec2D::new(x as _, y as _)].hazard = true;
+                game.grid[Vec2D::new(x as _, y as _)].hazards = 1;
             }
         }
 
         let mut floodfill = FloodFill::new(game.grid.width, game.grid.height);
-        floodfill.flood_snakes(&game.grid, &game.snakes);
+        floodfill.flood_snakes(&game);
         info!("Filled {} {floodfill:?}", floodfill.count_space(0));
         assert_eq!(floodfill.count_space(0), 96);
     }
@@ -599,7 +739,7 @@ mod test {
         info!("{:?}", game);
 
         let mut floodfill = FloodFill::new(game.grid.width, game.grid.height);
-        floodfill.flood_snakes(&game.grid, &game.snakes);
+        floodfill.flood_snakes(&game);
         info!("Filled {} {floodfill:?}", floodfill.count_space(0));
         assert_eq!(29, floodfill.count_space(0));
 
@@ -621,8 +761,187 @@ mod test {
         info!("{:?}", game);
 
         let mut floodfill = FloodFill::new(game.grid.width, game.grid.height);
-        floodfill.flood_snakes(&game.grid, &game.snakes);
+        floodfill.flood_snakes(&game);
         info!("Filled {} {floodfill:?}", floodfill.count_space(0));
         assert_eq!(35, floodfill.count_space(0));
     }
+
+    #[test]
+    fn flood_snakes_wrapped() {
+        use super::*;
+        use crate::game::{Game, Mode};
+        logging();
+
+        let mut game = Game::parse(
+            r#"
+            . . . . . . . . . . .
+            . . . . . . . . . . .
+            . . . . . . . . . . .
+            . . . . . . . . . . .
+            . . . . . . . . . . .
+            . . . . . . . . . . .
+            . . . . . . . . . . .
+            . . . . . . . . . . .
+            . . . . . . . . . . .
+            . . . . . . . . . . .
+            0 . . . . . . . . . 1"#,
+        )
+        .unwrap();
+
+        let mut floodfill = FloodFill::new(game.grid.width, game.grid.height);
+        floodfill.flood_snakes(&game);
+        info!("Filled {} {floodfill:?}", floodfill.count_space(0));
+        assert_eq!(floodfill.count_space(0), 54);
+
+        // The enemy is now directly left of us
+        game.set_mode(Mode::Wrapped);
+        floodfill.flood_snakes(&game);
+        info!("Filled {} {floodfill:?}", floodfill.count_space(0));
+        assert_eq!(floodfill.count_space(0), 54);
+        assert!(matches!(
+            floodfill[Vec2D::new(0, 10)],
+            FCell::Owned {
+                id: 0,
+                distance: 0,
+                ..
+            }
+        ));
+        assert!(matches!(
+            floodfill[Vec2D::new(10, 10)],
+            FCell::Owned {
+                id: 1,
+                distance: 0,
+                ..
+            }
+        ));
+    }
+
+    #[test]
+    fn flood_snakes_frozen() {
+        use super::*;
+        use crate::game::Game;
+        logging();
+
+        let mut game = Game::parse(
+            r#"
+            . . . . . . . . . . .
+            . . . . . . . . . . .
+            . . . . . . . . . . .
+            . . . . . . . . . . .
+            . . . . . . . . . . .
+            . . . . . . . . . . .
+            . . . . . . . . . . .
+            . . . . . . . . . . .
+            1 . . . . . . . . . .
+            ^ . . . . . . . . . .
+            ^ 0 . . . . . . . . ."#,
+        )
+        .unwrap();
+        game.freeze_distant(1);
+        assert_eq!(game.frozen.len(), 1);
+
+        // The frozen snake does not move its tail away
+        let mut floodfill = FloodFill::new(game.grid.width, game.grid.height);
+        floodfill.flood_snakes(&game);
+        info!("Filled {} {floodfill:?}", floodfill.count_space(0));
+        // Everything except the frozen body and our own head
+        assert_eq!(floodfill.count_space(0), 11 * 11 - 3 - 1);
+        assert!(matches!(
+            floodfill[Vec2D::new(0, 0)],
+            FCell::Occupied { id: u8::MAX, .. }
+        ));
+    }
+
+    #[test]
+    fn flood_snakes_constrictor() {
+        use super::*;
+        use crate::game::{Game, Mode};
+        logging();
+
+        let mut game = Game::parse(
+            r#"
+            . . . . .
+            > > > v .
+            . . 0 < .
+            . . . . .
+            . . . . ."#,
+        )
+        .unwrap();
+
+        // The tail moves away and frees the cells above
+        let mut floodfill = FloodFill::new(game.grid.width, game.grid.height);
+        floodfill.flood_snakes(&game);
+        info!("Filled {} {floodfill:?}", floodfill.count_space(0));
+        assert_eq!(floodfill.count_space(0), 5 * 5);
+
+        // Constrictor bodies remain as obstacles
+        game.set_mode(Mode::Constrictor);
+        floodfill.flood_snakes(&game);
+        info!("Filled {} {floodfill:?}", floodfill.count_space(0));
+        assert_eq!(floodfill.count_space(0), 5 * 5 - 6);
+    }
+
+    #[test]
+    fn flood_backends() {
+        use super::*;
+        use crate::agents::{FloodHeuristic, SoloHeuristic, TreeHeuristic};
+        use crate::search::Heuristic;
+        logging();
+
+        let game = Game::parse(
+            r#"
+            . . . . . . . . . . .
+            . . . . . . . . . . .
+            . . . . . . . . . . .
+            . . . 0 . . . . . . .
+            . . . ^ . . . . . . .
+            . . . ^ . . . . . . .
+            . . . . . . . . . . .
+            . . . . . . . 1 . . .
+            . . . . . . . ^ . . .
+            . . . . . . . ^ . . .
+            . . . . . . . . . . ."#,
+        )
+        .unwrap();
+        let area = |backend: FloodBackend, game: &Game| {
+            backend.flood(game, |flood_fill, food_distances| {
+                let space = (0..2)
+                    .map(|i| flood_fill.count_space(i))
+                    .collect::<Vec<_>>();
+                let health = (0..2)
+                    .map(|i| flood_fill.count_health(i))
+                    .collect::<Vec<_>>();
+                (space, health, food_distances.to_vec())
+            })
+        };
+
+        // Without food and hazards, the backends compute the same area control
+        let expected = area(FloodBackend::FloodFill, &game);
+        info!("{expected:?}");
+        assert_eq!(area(FloodBackend::BitBoard, &game), expected);
+
+        // The food distances are the same until the first food is eaten
+        let mut food = game.clone();
+        food.grid[Vec2D::new(3, 9)].t = CellT::Food;
+        food.grid[Vec2D::new(7, 1)].t = CellT::Food;
+        let (_, _, expected) = area(FloodBackend::FloodFill, &food);
+        let (_, _, food_distances) = area(FloodBackend::BitBoard, &food);
+        assert_eq!(expected.len(), 1);
+        assert_eq!(food_distances, expected);
+
+        // The heuristics select their backend in their config
+        let flood_fill = r#"{"flood_fill":"FloodFill"}"#;
+        let bitboard = r#"{"flood_fill":"BitBoard"}"#;
+        let parse = |config: &str| -> [Box<dyn Heuristic>; 3] {
+            [
+                Box::new(serde_json::from_str::<FloodHeuristic>(config).unwrap()),
+                Box::new(serde_json::from_str::<TreeHeuristic>(config).unwrap()),
+                Box::new(serde_json::from_str::<SoloHeuristic>(config).unwrap()),
+            ]
+        };
+        for (a, b) in parse(flood_fill).iter().zip(parse(bitboard).iter()) {
+            info!("{:?} {}", b, b.eval(&game));
+            assert_eq!(a.eval(&game), b.eval(&game));
+        }
+    }
 }
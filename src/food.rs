@@ -0,0 +1,302 @@
+use rand::prelude::*;
+use rand::rngs::SmallRng;
+
+use crate::env::{v2, Direction, Vec2D};
+use crate::game::{Game, Mode};
+use crate::grid::CellT;
+use crate::rules::Map;
+
+/// Policy for placing food on the board.
+pub trait FoodSpawner {
+    /// Places the initial food at the start of the game.
+    fn init(&self, game: &mut Game, rng: &mut SmallRng);
+    /// Spawns new food after each turn.
+    fn spawn(&self, game: &mut Game, rng: &mut SmallRng);
+}
+
+/// Selects the food placement for the game mode and map.
+pub fn food_spawner(game: &Game, map: Map) -> Box<dyn FoodSpawner> {
+    match game.mode {
+        // Constrictor snakes grow without food
+        Mode::Constrictor => Box::new(NoFood),
+        _ => match map.food_spawns(&game.grid) {
+            Some(cells) => Box::new(MapFood(cells)),
+            None => Box::new(StandardFood),
+        },
+    }
+}
+
+/// No food at all.
+pub struct NoFood;
+
+impl FoodSpawner for NoFood {
+    fn init(&self, _game: &mut Game, _rng: &mut SmallRng) {}
+    fn spawn(&self, _game: &mut Game, _rng: &mut SmallRng) {}
+}
+
+/// Places `n` food on random `cells` that are free, not hazardous,
+/// and not reachable by a snake in the next turn.
+fn place_randomly(
+    game: &mut Game,
+    cells: impl IntoIterator<Item = Vec2D>,
+    n: usize,
+    rng: &mut SmallRng,
+) {
+    let grid = &game.grid;
+    let mut candidates = cells
+        .into_iter()
+        .filter(|&p| grid[p].t == CellT::Free && grid[p].hazards == 0)
+        .filter(|&p| {
+            !game
+                .snakes
+                .iter()
+                .filter(|s| s.alive())
+                .any(|s| Direction::iter().any(|d| grid.neighbor(s.head(), d) == Some(p)))
+        })
+        .collect::<Vec<_>>();
+    candidates.shuffle(rng);
+    for p in candidates.into_iter().take(n) {
+        game.grid[p].t = CellT::Food;
+    }
+}
+
+/// Spawns the missing `minimum_food`, or otherwise a single food
+/// with the `food_spawn_chance`, on random `cells`.
+fn spawn_randomly(game: &mut Game, cells: impl IntoIterator<Item = Vec2D>, rng: &mut SmallRng) {
+    let settings = game.settings;
+    let food_count = game
+        .grid
+        .cells
+        .iter()
+        .filter(|c| c.t == CellT::Food)
+        .count();
+    if food_count < settings.minimum_food {
+        place_randomly(game, cells, settings.minimum_food - food_count, rng);
+    } else if settings.food_spawn_chance > 0
+        && 100 - rng.gen_range(0..100) < settings.food_spawn_chance as usize
+    {
+        place_randomly(game, cells, 1, rng);
+    }
+}
+
+/// All cells of the board.
+fn all_cells(game: &Game) -> impl Iterator<Item = Vec2D> {
+    let (width, height) = (game.grid.width as i16, game.grid.height as i16);
+    (0..height).flat_map(move |y| (0..width).map(move |x| v2(x, y)))
+}
+
+/// Food placement of the official standard ruleset.
+pub struct StandardFood;
+
+impl FoodSpawner for StandardFood {
+    fn init(&self, game: &mut Game, rng: &mut SmallRng) {
+        let (width, height) = (game.grid.width as i16, game.grid.height as i16);
+        let center = v2((width - 1) / 2, (height - 1) / 2);
+
+        // Up to 4 snakes get food nearby on small boards
+        if game.snakes.len() <= 4 || width * height >= 11 * 11 {
+            for snake in game.snakes.clone() {
+                let head = snake.head();
+                let p = [v2(-1, -1), v2(-1, 1), v2(1, -1), v2(1, 1)]
+                    .into_iter()
+                    .map(|p| head + p)
+                    .filter(|&p| p != center && game.grid.has(p))
+                    .filter(|&p| game.grid[p].t == CellT::Free && game.grid[p].hazards == 0)
+                    // Further away from the center than the snake on one axis
+                    .filter(|&p| {
+                        (p.x < head.x && head.x < center.x)
+                            || (center.x < head.x && head.x < p.x)
+                            || (p.y < head.y && head.y < center.y)
+                            || (center.y < head.y && head.y < p.y)
+                    })
+                    // No food in the corners
+                    .filter(|&p| {
+                        !((p.x == 0 || p.x == width - 1) && (p.y == 0 || p.y == height - 1))
+                    })
+                    .choose(rng);
+                if let Some(p) = p {
+                    game.grid[p].t = CellT::Food;
+                }
+            }
+        }
+
+        // Food at center
+        if game.grid[center].t == CellT::Free {
+            game.grid[center].t = CellT::Food;
+        }
+    }
+
+    fn spawn(&self, game: &mut Game, rng: &mut SmallRng) {
+        spawn_randomly(game, all_cells(game), rng);
+    }
+}
+
+/// Food that only spawns on the fixed cells of a map.
+pub struct MapFood(pub Vec<Vec2D>);
+
+impl FoodSpawner for MapFood {
+    fn init(&self, game: &mut Game, rng: &mut SmallRng) {
+        let n = game.settings.minimum_food.max(1);
+        place_randomly(game, self.0.iter().copied(), n, rng);
+    }
+
+    fn spawn(&self, game: &mut Game, rng: &mut SmallRng) {
+        spawn_randomly(game, self.0.iter().copied(), rng);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::logging;
+    use log::info;
+
+    fn food(game: &super::Game) -> Vec<super::Vec2D> {
+        use super::*;
+        all_cells(game)
+            .filter(|&p| game.grid[p].t == CellT::Food)
+            .collect()
+    }
+
+    #[test]
+    fn standard_init() {
+        use super::*;
+        use crate::game::Snake;
+        logging();
+
+        let heads = [v2(1, 1), v2(9, 1), v2(9, 9), v2(1, 9)];
+        let snakes = heads
+            .iter()
+            .map(|&p| Snake::new(vec![p; 3].into(), 100))
+            .collect();
+        let game = Game::new(0, Mode::Standard, 11, 11, snakes, &[], &[]);
+
+        for seed in 0..20 {
+            let mut game = game.clone();
+            StandardFood.init(&mut game, &mut SmallRng::seed_from_u64(seed));
+            info!("{game:?}");
+
+            let food = food(&game);
+            assert_eq!(food.len(), heads.len() + 1);
+            assert!(food.contains(&v2(5, 5)));
+            // One diagonal food behind each snake, but never in a corner
+            for head in heads {
+                let p = food
+                    .iter()
+                    .find(|p| (p.x - head.x).abs() == 1 && (p.y - head.y).abs() == 1)
+                    .unwrap();
+                assert!(
+                    (p.x - 5).abs() > (head.x - 5).abs() || (p.y - 5).abs() > (head.y - 5).abs()
+                );
+                assert!(!((p.x == 0 || p.x == 10) && (p.y == 0 || p.y == 10)));
+            }
+        }
+
+        // Deterministic for the same seed
+        let mut a = game.clone();
+        let mut b = game;
+        StandardFood.init(&mut a, &mut SmallRng::seed_from_u64(42));
+        StandardFood.init(&mut b, &mut SmallRng::seed_from_u64(42));
+        assert_eq!(food(&a), food(&b));
+    }
+
+    #[test]
+    fn standard_spawn() {
+        use super::*;
+        use crate::game::Snake;
+        logging();
+
+        let snakes = vec![Snake::new(vec![v2(5, 3), v2(5, 4), v2(5, 5)].into(), 100)];
+        let game = Game::new(0, Mode::Standard, 11, 11, snakes, &[], &[]);
+        let mut rng = SmallRng::seed_from_u64(42);
+
+        // The minimum food is placed at once, but not next to the head
+        let mut settings = game.settings;
+        settings.minimum_food = 3;
+        settings.food_spawn_chance = 0;
+        let mut min_game = game.clone();
+        min_game.set_settings(settings);
+        for _ in 0..100 {
+            let mut game = min_game.clone();
+            StandardFood.spawn(&mut game, &mut rng);
+            let food = food(&game);
+            assert_eq!(food.len(), 3);
+            assert!(food.iter().all(|&p| (p - v2(5, 5)).manhattan() > 1));
+        }
+        // Without a spawn chance, nothing is added beyond the minimum
+        let mut game = min_game.clone();
+        for _ in 0..100 {
+            StandardFood.spawn(&mut game, &mut rng);
+        }
+        assert_eq!(food(&game).len(), 3);
+
+        // Single food with the spawn chance
+        settings.minimum_food = 0;
+        settings.food_spawn_chance = 25;
+        let mut chance_game = game.clone();
+        chance_game.set_settings(settings);
+        let mut spawned = 0;
+        for _ in 0..1000 {
+            let mut game = chance_game.clone();
+            StandardFood.spawn(&mut game, &mut rng);
+            let n = food(&game).len() - 3;
+            assert!(n <= 1);
+            spawned += n;
+        }
+        info!("spawned {spawned}/1000");
+        assert!((200..300).contains(&spawned));
+
+        // Only free cells without hazards are selected
+        let free = [v2(0, 0), v2(10, 10)];
+        let snakes = game.snakes.clone();
+        let hazards = all_cells(&game)
+            .filter(|p| !free.contains(p) && !snakes[0].body.contains(p))
+            .collect::<Vec<_>>();
+        let mut game = Game::new(0, Mode::Standard, 11, 11, snakes, &[], &hazards);
+        game.set_settings(min_game.settings);
+        StandardFood.spawn(&mut game, &mut rng);
+        let mut food = food(&game);
+        food.sort_by_key(|p| (p.x, p.y));
+        assert_eq!(food, free.to_vec());
+    }
+
+    #[test]
+    fn map_and_no_food() {
+        use super::*;
+        use crate::game::Snake;
+        logging();
+
+        let snakes = vec![Snake::new(vec![v2(1, 1); 3].into(), 100)];
+        let mut game = Game::new(0, Mode::Standard, 11, 11, snakes, &[], &[]);
+        let mut settings = game.settings;
+        settings.minimum_food = 2;
+        settings.food_spawn_chance = 50;
+        game.set_settings(settings);
+        let mut rng = SmallRng::seed_from_u64(42);
+
+        // Food only spawns on the map cells, excluding the ones next to the head
+        let cells = vec![v2(1, 2), v2(3, 3), v2(5, 5), v2(7, 7), v2(9, 9)];
+        let spawner = MapFood(cells.clone());
+        let mut map_game = game.clone();
+        spawner.init(&mut map_game, &mut rng);
+        assert_eq!(food(&map_game).len(), 2);
+        for _ in 0..100 {
+            spawner.spawn(&mut map_game, &mut rng);
+            assert!(food(&map_game).iter().all(|p| cells.contains(p)));
+        }
+        assert_eq!(food(&map_game).len(), cells.len() - 1);
+
+        let mut no_food = game.clone();
+        NoFood.init(&mut no_food, &mut rng);
+        NoFood.spawn(&mut no_food, &mut rng);
+        assert!(food(&no_food).is_empty());
+
+        // Selection by mode and map
+        let mut constrictor = game.clone();
+        constrictor.mode = Mode::Constrictor;
+        food_spawner(&constrictor, Map::Standard).init(&mut constrictor, &mut rng);
+        assert!(food(&constrictor).is_empty());
+        let mut standard = game;
+        food_spawner(&standard, Map::Standard).init(&mut standard, &mut rng);
+        assert!(food(&standard).contains(&v2(5, 5)));
+    }
+}
@@ -4,9 +4,10 @@ use std::fmt::{self, Debug};
 
 use owo_colors::{AnsiColors, OwoColorize};
 
-use crate::env::{Direction, GameRequest, Battlesnake, Vec2D, HAZARD_DAMAGE};
+use crate::env::{Battlesnake, Direction, GameRequest, RulesetSettings, Vec2D};
 use crate::grid::{Cell, CellT, Grid};
 use crate::util::OrdPair;
+use crate::zobrist;
 
 /// The outcome of a simulated game.
 /// If the game did not end the outcome is `None`.
@@ -17,16 +18,47 @@ pub enum Outcome {
     Winner(u8),
 }
 
+/// The ruleset mode of a game.
+#[derive(PartialEq, Eq, Debug, Clone, Copy, Default)]
+pub enum Mode {
+    #[default]
+    Standard,
+    /// Snakes that leave the board enter it on the opposite side.
+    Wrapped,
+    /// Snakes grow every turn and never lose health.
+    Constrictor,
+    /// Snakes of the same squad play together.
+    Squad,
+}
+
+impl From<&str> for Mode {
+    /// Returns the mode for a ruleset name (e.g. "wrapped").
+    fn from(name: &str) -> Self {
+        match name {
+            "wrapped" => Self::Wrapped,
+            "constrictor" => Self::Constrictor,
+            "squad" => Self::Squad,
+            _ => Self::Standard,
+        }
+    }
+}
+
 /// Reduced representation of a snake.
 #[derive(Debug, Clone)]
 pub struct Snake {
     /// tail to head
     pub body: VecDeque<Vec2D>,
     pub health: u8,
+    /// Squad of the snake in squad games.
+    pub squad: Option<u8>,
 }
 impl Snake {
     pub fn new(body: VecDeque<Vec2D>, health: u8) -> Self {
-        Self { body, health }
+        Self {
+            body,
+            health,
+            squad: None,
+        }
     }
 
     #[must_use]
@@ -41,6 +73,50 @@ impl Snake {
     pub fn head(&self) -> Vec2D {
         *self.body.back().unwrap()
     }
+
+    /// Zobrist key of the head, tail, length, and health of the snake.
+    fn summary(&self, id: usize) -> u64 {
+        let tail = self.body[0];
+        let stacked = self.body.iter().skip(1).take_while(|&&p| p == tail).count();
+        zobrist::summary(id, self.head(), tail, stacked, self.body.len(), self.health)
+    }
+
+    /// Zobrist key of the connections between the body segments.
+    fn body_hash(&self, id: usize) -> u64 {
+        self.body
+            .iter()
+            .zip(self.body.iter().skip(1))
+            .filter(|(from, to)| from != to)
+            .fold(0, |h, (&from, &to)| h ^ zobrist::body(id, from, to))
+    }
+
+    /// Returns if both snakes are in the same squad.
+    /// Note that every snake with a squad is allied with itself.
+    pub fn allied(&self, other: &Snake) -> bool {
+        self.squad.is_some() && self.squad == other.squad
+    }
+}
+
+/// Record of the changes of `Game::apply`, that are reverted by `Game::undo`.
+#[derive(Debug)]
+#[must_use]
+pub struct Undo {
+    hash: u64,
+    snakes: Vec<Option<SnakeUndo>>,
+}
+
+/// Changes of a snake that was alive before the move.
+#[derive(Debug)]
+struct SnakeUndo {
+    health: u8,
+    len: usize,
+    tail: Vec2D,
+    /// The new head and the previous cell at its position
+    head: Option<(Vec2D, Cell)>,
+    /// If the new head is still part of the body
+    head_pushed: bool,
+    /// Body of the snake if it died
+    body: Option<VecDeque<Vec2D>>,
 }
 
 /// Game represents holds the complete game state.
@@ -48,10 +124,17 @@ impl Snake {
 #[derive(Clone)]
 pub struct Game {
     pub turn: usize,
+    pub mode: Mode,
+    pub settings: RulesetSettings,
     pub grid: Grid,
     /// All snakes. Dead ones have health = 0 and no body.
     /// The ids have to be the same as the indices!
     pub snakes: Vec<Snake>,
+    /// Snakes that are no longer simulated and only remain as obstacles.
+    /// They are considered to be alive.
+    pub frozen: Vec<Snake>,
+    /// Zobrist hash of the turn, food, and simulated snakes.
+    hash: u64,
 }
 
 impl Game {
@@ -59,6 +142,7 @@ impl Game {
     #[must_use]
     pub fn new(
         turn: usize,
+        mode: Mode,
         width: usize,
         height: usize,
         snakes: Vec<Snake>,
@@ -66,6 +150,7 @@ impl Game {
         hazards: &[Vec2D],
     ) -> Self {
         let mut grid = Grid::new(width, height);
+        grid.wrapped = mode == Mode::Wrapped;
         grid.add_food(food);
         grid.add_hazards(hazards);
 
@@ -73,57 +158,130 @@ impl Game {
             grid.add_snake(snake.body.iter().copied());
         }
 
-        Self { turn, snakes, grid }
+        let mut game = Self {
+            turn,
+            mode,
+            settings: RulesetSettings::default(),
+            snakes,
+            frozen: Vec::new(),
+            grid,
+            hash: 0,
+        };
+        game.rehash();
+        game
+    }
+
+    /// Returns the Zobrist hash of the game state.
+    ///
+    /// It is updated incrementally by `step`, `apply`, and `undo`.
+    /// Hazards and frozen snakes are not part of the hash.
+    pub fn hash(&self) -> u64 {
+        self.hash
+    }
+
+    /// Recomputes the hash, which is necessary after changing the turn,
+    /// food, or snakes directly.
+    pub fn rehash(&mut self) {
+        let mut hash = zobrist::turn(self.turn);
+        for y in 0..self.grid.height as i16 {
+            for x in 0..self.grid.width as i16 {
+                if self.grid[Vec2D::new(x, y)].t == CellT::Food {
+                    hash ^= zobrist::food(Vec2D::new(x, y));
+                }
+            }
+        }
+        for (i, snake) in self.snakes.iter().enumerate() {
+            if snake.alive() {
+                hash ^= snake.body_hash(i) ^ snake.summary(i);
+            }
+        }
+        self.hash = hash;
     }
 
     /// Loads the game state from the provided request.
     #[must_use]
     pub fn from_request(request: &GameRequest) -> Self {
-        let mut snakes = Vec::with_capacity(4);
-        snakes.push(Snake::from(&request.you));
-
-        // Only look at the nearest four snakes
-        if request.board.snakes.len() > 4 {
-            let mut queue = BinaryHeap::new();
-            for snake in request
+        let battlesnakes = std::iter::once(&request.you).chain(
+            request
                 .board
                 .snakes
                 .iter()
-                .filter(|s| s.id != request.you.id)
-                .map(Snake::from)
-            {
-                let body_dist = snake
-                    .body
-                    .iter()
-                    .map(|&p| (p - snakes[0].head()).manhattan())
-                    .min()
-                    .unwrap_or_default();
-                queue.push(OrdPair(Reverse(body_dist), snake));
-            }
+                .filter(|s| s.id != request.you.id),
+        );
 
-            for _ in 1..3 {
-                if let Some(OrdPair(_, snake)) = queue.pop() {
-                    snakes.push(snake);
-                }
+        // Squads are numbered in the order of their first member
+        let mut squads = Vec::new();
+        let mut snakes = Vec::with_capacity(request.board.snakes.len());
+        for battlesnake in battlesnakes {
+            let mut snake = Snake::from(battlesnake);
+            if !battlesnake.squad.is_empty() {
+                let squad = match squads.iter().position(|&s| s == battlesnake.squad) {
+                    Some(squad) => squad,
+                    None => {
+                        squads.push(battlesnake.squad.as_str());
+                        squads.len() - 1
+                    }
+                };
+                snake.squad = Some(squad as u8);
             }
-        } else {
-            snakes.extend(
-                request
-                    .board
-                    .snakes
-                    .iter()
-                    .filter(|s| s.id != request.you.id)
-                    .map(Snake::from),
-            );
+            snakes.push(snake);
         }
-        Self::new(
+
+        let mut game = Self::new(
             request.turn,
+            Mode::from(request.game.ruleset.name.as_str()),
             request.board.width,
             request.board.height,
             snakes,
             &request.board.food,
             &request.board.hazards,
-        )
+        );
+        game.set_settings(request.game.ruleset.settings);
+        game
+    }
+
+    /// Changes the ruleset mode, including the topology of the grid.
+    pub fn set_mode(&mut self, mode: Mode) {
+        self.mode = mode;
+        self.grid.wrapped = mode == Mode::Wrapped;
+    }
+
+    /// Changes the ruleset settings, including the hazard damage of the grid.
+    pub fn set_settings(&mut self, settings: RulesetSettings) {
+        self.settings = settings;
+        self.grid.hazard_damage = settings.hazard_damage_per_turn;
+    }
+
+    /// Freezes all but the `n` snakes that are nearest to the snake 0.
+    ///
+    /// Frozen snakes are no longer moved, but their bodies remain as obstacles
+    /// on the grid. This limits the number of simulated snakes in a search,
+    /// without ignoring the distant snakes entirely.
+    pub fn freeze_distant(&mut self, n: usize) {
+        if self.snakes.len() <= n {
+            return;
+        }
+
+        let head = self.snakes[0].body.back().copied().unwrap_or_default();
+        let mut queue = BinaryHeap::new();
+        for snake in self.snakes.drain(1..).filter(Snake::alive) {
+            let body_dist = snake
+                .body
+                .iter()
+                .map(|&p| (p - head).manhattan())
+                .min()
+                .unwrap_or_default();
+            queue.push(OrdPair(Reverse(body_dist), snake));
+        }
+
+        while let Some(OrdPair(_, snake)) = queue.pop() {
+            if self.snakes.len() < n {
+                self.snakes.push(snake);
+            } else {
+                self.frozen.push(snake);
+            }
+        }
+        self.rehash();
     }
 
     /// Returns if the game has ended and which snake is the winner or if the
@@ -137,9 +295,19 @@ impl Game {
                 survivor = i as u8;
             }
         }
-        match living_snakes {
+        match living_snakes + self.frozen.len() {
             0 => Outcome::Match,
-            1 => Outcome::Winner(survivor),
+            1 if self.frozen.is_empty() => Outcome::Winner(survivor),
+            // The first member of the last living squad wins
+            _ if self.mode == Mode::Squad && self.frozen.is_empty() => {
+                let mut living = self.snakes.iter().enumerate().filter(|(_, s)| s.alive());
+                let (first, snake) = living.next().unwrap();
+                if living.all(|(_, s)| s.allied(snake)) {
+                    Outcome::Winner(first as u8)
+                } else {
+                    Outcome::None
+                }
+            }
             _ => Outcome::None,
         }
     }
@@ -152,7 +320,7 @@ impl Game {
     /// Returns all valid moves that do not immediately kill the snake.
     /// Head to head collisions are not considered.
     #[inline]
-    pub fn valid_moves(&self, snake: u8) -> ValidMoves {
+    pub fn valid_moves(&self, snake: u8) -> ValidMoves<'_> {
         if self.snake_is_alive(snake) {
             ValidMoves::new(self, &self.snakes[snake as usize])
         } else {
@@ -168,98 +336,269 @@ impl Game {
 
     #[inline]
     fn snake_move_is_valid(&self, snake: &Snake, dir: Direction) -> bool {
-        let p = snake.head().apply(dir);
         // Free or occupied by tail (free in the next turn)
-        self.grid.has(p)
-            && (self.grid[p].t != CellT::Owned
+        if let Some(p) = self.grid.neighbor(snake.head(), dir) {
+            self.grid[p].t != CellT::Owned
                 || self
                     .snakes
                     .iter()
                     .filter(|s| s.alive())
-                    .any(|s| p == s.body[0] && p != s.body[1]))
+                    .any(|s| p == s.body[0] && p != s.body[1])
+                // Or occupied by the body of a squad member
+                || self.squad_body_collisions()
+                    && self.snakes.iter().any(|s| {
+                        s.alive() && s.allied(snake) && s.head() != snake.head() && s.body.contains(&p)
+                    })
+        } else {
+            false
+        }
+    }
+
+    /// Returns if squad members can move over each other.
+    fn squad_body_collisions(&self) -> bool {
+        self.mode == Mode::Squad && self.settings.squad.allow_body_collisions
     }
 
     /// Executed the provided moves for each living agent.
     /// This method also checks for eating and collision with walls or other snakes.
+    ///
+    /// Like the official rules, snakes that leave the board or starve are
+    /// eliminated first. The remaining snakes are then checked for collisions
+    /// with each other, before any of these collisions are applied.
     pub fn step(&mut self, moves: &[Direction]) {
+        let _ = self.apply(moves);
+    }
+
+    /// Executes the moves like `step` and returns a record of the changes,
+    /// which can be reverted with `undo`.
+    ///
+    /// This is faster than cloning the game before each step.
+    pub fn apply(&mut self, moves: &[Direction]) -> Undo {
         assert!(moves.len() >= self.snakes.len());
+        assert!(self.snakes.len() <= 64, "Too many snakes");
+
+        let mut undo = Undo {
+            hash: self.hash,
+            snakes: Vec::with_capacity(self.snakes.len()),
+        };
+        self.hash ^= zobrist::turn(self.turn) ^ zobrist::turn(self.turn + 1);
 
         // Pop tail
-        for snake in &mut self.snakes {
+        for (id, snake) in self.snakes.iter_mut().enumerate() {
             if snake.alive() {
+                let len = snake.body.len();
+                self.hash ^= snake.summary(id);
                 let tail = snake.body.pop_front().unwrap();
                 let new_tail = snake.body[0];
                 if tail != new_tail {
+                    self.hash ^= zobrist::body(id, tail, new_tail);
                     self.grid[tail].t = CellT::Free;
                 }
+                undo.snakes.push(Some(SnakeUndo {
+                    health: snake.health,
+                    len,
+                    tail,
+                    head: None,
+                    head_pushed: false,
+                    body: None,
+                }));
+            } else {
+                undo.snakes.push(None);
             }
         }
 
         // Move head & eat
-        for (id, snake) in self.snakes.iter_mut().enumerate() {
-            if snake.alive() {
-                let dir = moves[id];
-                let head = snake.head().apply(dir);
-
-                if !self.grid.has(head) {
-                    snake.health = 0;
-                    continue;
-                }
-
-                snake.body.push_back(head);
+        for (id, (snake, record)) in self.snakes.iter_mut().zip(&mut undo.snakes).enumerate() {
+            if let Some(record) = record {
+                let head = match self.grid.neighbor(snake.head(), moves[id]) {
+                    Some(head) => head,
+                    None => {
+                        // Out of bounds
+                        snake.health = 0;
+                        self.grid.remove_snake(snake.body.iter().copied());
+                        continue;
+                    }
+                };
 
                 let g_cell = self.grid[head];
-                if g_cell.t == CellT::Owned {
-                    snake.health = 0;
-                    continue;
-                }
-
-                snake.health = if g_cell.t == CellT::Food {
-                    snake.body.push_front(*snake.body.front().unwrap());
+                record.head = Some((head, g_cell));
+                // Constrictor snakes grow as if they eat every turn
+                snake.health = if g_cell.t == CellT::Food || self.mode == Mode::Constrictor {
+                    let tail = *snake.body.front().unwrap();
+                    snake.body.push_front(tail);
                     100
                 } else {
-                    snake
-                        .health
-                        .saturating_sub(if g_cell.hazard { HAZARD_DAMAGE } else { 1 })
+                    snake.health.saturating_sub(self.grid.damage(head))
                 };
+
+                if snake.alive() {
+                    // The head is added to the grid after the collision checks
+                    self.hash ^= zobrist::body(id, snake.head(), head);
+                    snake.body.push_back(head);
+                    record.head_pushed = true;
+                } else {
+                    // Starved snakes are no obstacles for the others
+                    self.grid.remove_snake(snake.body.iter().copied());
+                }
             }
         }
 
-        // Check head to head
-        // Warning: This is only accurate for head to head on two snakes but not more
-        for i in 0..self.snakes.len() - 1 {
-            if self.snakes[i].alive() {
-                for j in i + 1..self.snakes.len() {
-                    if self.snakes[j].alive() && self.snakes[i].head() == self.snakes[j].head() {
-                        use std::cmp::Ordering;
-                        match self.snakes[i].body.len().cmp(&self.snakes[j].body.len()) {
-                            Ordering::Less => self.snakes[i].health = 0,
-                            Ordering::Greater => self.snakes[j].health = 0,
-                            Ordering::Equal => {
-                                self.snakes[i].health = 0;
-                                self.snakes[j].health = 0;
-                            }
-                        }
-                    }
+        let squad_body_collisions = self.squad_body_collisions();
+        if squad_body_collisions {
+            // Restore bodies of squad members that were cleared by other tails
+            for snake in self.snakes.iter().filter(|s| s.alive()) {
+                self.grid
+                    .add_snake(snake.body.iter().rev().skip(1).copied());
+            }
+        }
+
+        // Check collisions on the state before any of them are applied
+        let mut collided = 0_u64;
+        for (i, snake) in self.snakes.iter().enumerate() {
+            if snake.alive() {
+                let head = snake.head();
+                // Body collision (heads are not yet on the grid)
+                if self.grid[head].t == CellT::Owned
+                    // Squad members may move over each other
+                    && !(squad_body_collisions
+                        && self.snakes.iter().enumerate().any(|(j, other)| {
+                            i != j
+                                && other.alive()
+                                && other.allied(snake)
+                                && other.body.iter().rev().skip(1).any(|&p| p == head)
+                        }))
+                    // Head to head with an equal or longer snake
+                    || self.snakes.iter().enumerate().any(|(j, other)| {
+                        i != j
+                            && other.alive()
+                            && other.head() == head
+                            && other.body.len() >= snake.body.len()
+                    })
+                {
+                    collided |= 1 << i;
                 }
             }
         }
 
-        // Clear died snakes
+        // Apply collisions and clear died snakes
         let grid = &mut self.grid;
-        for snake in &mut self.snakes {
-            if snake.alive() {
-                let head_cell = &mut grid[snake.head()];
-                head_cell.t = CellT::Owned;
-            } else if !snake.body.is_empty() {
-                for &p in &snake.body {
-                    grid[p].t = CellT::Free;
+        for (i, (snake, record)) in self.snakes.iter_mut().zip(&mut undo.snakes).enumerate() {
+            if collided & (1 << i) != 0 {
+                snake.health = 0;
+                // The head might be on the body of another snake
+                let head = snake.body.pop_back().unwrap();
+                self.hash ^= zobrist::body(i, snake.head(), head);
+                if let Some(record) = record {
+                    record.head_pushed = false;
+                }
+                // Food is consumed even if the snake died
+                if grid[head].t == CellT::Food {
+                    grid[head].t = CellT::Free;
+                    self.hash ^= zobrist::food(head);
+                }
+                grid.remove_snake(snake.body.iter().copied());
+            } else if snake.alive() {
+                let head = snake.head();
+                if grid[head].t == CellT::Food {
+                    self.hash ^= zobrist::food(head);
+                }
+                grid[head].t = CellT::Owned;
+            }
+        }
+
+        if self.mode == Mode::Squad {
+            self.share_squads();
+        }
+
+        // Move the bodies of died snakes into the undo record
+        for (id, (snake, record)) in self.snakes.iter_mut().zip(&mut undo.snakes).enumerate() {
+            if let Some(record) = record {
+                if snake.alive() {
+                    self.hash ^= snake.summary(id);
+                } else {
+                    self.hash ^= snake.body_hash(id);
+                    record.body = Some(std::mem::take(&mut snake.body));
                 }
-                snake.body.clear();
             }
         }
 
         self.turn += 1;
+        undo
+    }
+
+    /// Reverts the changes of `apply`, restoring the exact previous state.
+    /// The records have to be undone in the reverse order of their creation.
+    pub fn undo(&mut self, undo: Undo) {
+        self.turn -= 1;
+        self.hash = undo.hash;
+
+        // Restore the cells of the new heads, before the old bodies are added
+        for record in undo.snakes.iter().flatten() {
+            if let Some((head, cell)) = record.head {
+                self.grid[head] = cell;
+            }
+        }
+
+        for (snake, record) in self.snakes.iter_mut().zip(undo.snakes) {
+            if let Some(record) = record {
+                if let Some(body) = record.body {
+                    snake.body = body;
+                }
+                if record.head_pushed {
+                    snake.body.pop_back();
+                }
+                // Remove the growth at the tail
+                while snake.body.len() >= record.len {
+                    snake.body.pop_front();
+                }
+                snake.body.push_front(record.tail);
+                snake.health = record.health;
+                self.grid.add_snake(snake.body.iter().copied());
+            }
+        }
+    }
+
+    /// Applies the shared elimination, health, and length of the squad rules.
+    fn share_squads(&mut self) {
+        let settings = self.settings.squad;
+        let num_squads = self.snakes.iter().filter_map(|s| s.squad).max();
+        for squad in (0..=num_squads.unwrap_or_default()).map(Some) {
+            if settings.shared_elimination
+                && self.snakes.iter().any(|s| s.squad == squad && !s.alive())
+            {
+                for snake in &mut self.snakes {
+                    if snake.squad == squad && snake.alive() {
+                        snake.health = 0;
+                        self.grid.remove_snake(snake.body.iter().copied());
+                    }
+                }
+                continue;
+            }
+
+            let members = || self.snakes.iter().filter(|s| s.squad == squad && s.alive());
+            let health = members().map(|s| s.health).max().unwrap_or_default();
+            let len = members().map(|s| s.body.len()).max().unwrap_or_default();
+            for snake in &mut self.snakes {
+                if snake.squad == squad && snake.alive() {
+                    if settings.shared_health {
+                        snake.health = health;
+                    }
+                    if settings.shared_length {
+                        while snake.body.len() < len {
+                            let tail = *snake.body.front().unwrap();
+                            snake.body.push_front(tail);
+                        }
+                    }
+                }
+            }
+        }
+
+        if settings.allow_body_collisions {
+            // Restore the bodies that were removed from overlapping cells
+            for snake in self.snakes.iter().filter(|s| s.alive()) {
+                self.grid.add_snake(snake.body.iter().copied());
+            }
+        }
     }
 }
 
@@ -295,7 +634,7 @@ impl Game {
             .collect();
         let height = txt.lines().count();
 
-        if raw_cells.len() % height != 0 {
+        if !raw_cells.len().is_multiple_of(height) {
             return None;
         }
         let width = raw_cells.len() / height;
@@ -303,9 +642,9 @@ impl Game {
         let mut grid = Grid::new(width, height);
         for (i, cell) in raw_cells.iter().enumerate() {
             grid[Vec2D::new((i % width) as _, (i / width) as _)] = match cell {
-                RawCell::Free => Cell::new(CellT::Free, false),
-                RawCell::Food => Cell::new(CellT::Food, false),
-                _ => Cell::new(CellT::Owned, false),
+                RawCell::Free => Cell::new(CellT::Free, 0),
+                RawCell::Food => Cell::new(CellT::Food, 0),
+                _ => Cell::new(CellT::Owned, 0),
             }
         }
 
@@ -320,7 +659,7 @@ impl Game {
                     (next.within(width, height)
                         && raw_cells[(next.x + next.y * width as i16) as usize]
                             == RawCell::Body(d.invert()))
-                    .then(|| next)
+                    .then_some(next)
                 }) {
                     p = next;
                     body.push_front(p);
@@ -334,11 +673,17 @@ impl Game {
             }
         }
 
-        Some(Self {
+        let mut game = Self {
             turn: 0,
+            mode: Mode::Standard,
+            settings: RulesetSettings::default(),
             grid,
             snakes,
-        })
+            frozen: Vec::new(),
+            hash: 0,
+        };
+        game.rehash();
+        Some(game)
     }
 }
 
@@ -387,11 +732,11 @@ impl Debug for Game {
                 } else {
                     FmtCell::Free
                 };
-                cell.1 = g_cell.hazard;
+                cell.1 = g_cell.hazards > 0;
             }
         }
 
-        for (id, snake) in self.snakes.iter().enumerate() {
+        for (id, snake) in self.snakes.iter().chain(&self.frozen).enumerate() {
             if !snake.alive() || snake.body.is_empty() {
                 continue;
             }
@@ -400,7 +745,7 @@ impl Debug for Game {
 
             for next_body in snake.body.iter().skip(1).copied() {
                 cells[last_body.y as usize * self.grid.width + last_body.x as usize].0 =
-                    FmtCell::Tail(Direction::from(next_body - last_body), id as _);
+                    FmtCell::Tail(self.grid.direction(last_body, next_body), id as _);
 
                 last_body = next_body;
             }
@@ -428,13 +773,17 @@ impl Debug for Game {
         // Snakes
         write!(f, "  Snakes: [")?;
         let mut first = true;
-        for (id, snake) in self.snakes.iter().enumerate() {
+        for (id, snake) in self.snakes.iter().chain(&self.frozen).enumerate() {
             if !first {
                 write!(f, ", ")?;
             } else {
                 first = false;
             }
-            write!(f, "({id}: {})", snake.health)?;
+            if id < self.snakes.len() {
+                write!(f, "({id}: {})", snake.health)?;
+            } else {
+                write!(f, "({id}: {} frozen)", snake.health)?;
+            }
         }
         writeln!(f, "]")?;
 
@@ -596,6 +945,188 @@ mod test {
         }
     }
 
+    #[test]
+    fn game_step_head_to_head_three() {
+        use super::*;
+        use Direction::*;
+        logging();
+
+        // The longest snake survives
+        let mut game = Game::parse(
+            r#"
+            . . . . . . .
+            v . . . . . .
+            v . . . . . v
+            > > 0 . 1 < <
+            . . . 2 . . .
+            . . . ^ . . .
+            . . . ^ . . ."#,
+        )
+        .unwrap();
+        game.step(&[Right, Left, Up]);
+        info!("{game:?}");
+        assert!(game.snake_is_alive(0));
+        assert!(!game.snake_is_alive(1));
+        assert!(!game.snake_is_alive(2));
+        assert_eq!(game.snakes[0].head(), Vec2D::new(3, 3));
+        assert!(game.grid[Vec2D::new(3, 3)].t == CellT::Owned);
+        assert!(game.grid[Vec2D::new(5, 3)].t == CellT::Free);
+        assert!(game.grid[Vec2D::new(3, 1)].t == CellT::Free);
+
+        // Two equally long snakes kill each other and the shorter one
+        let mut game = Game::parse(
+            r#"
+            . . . . . . .
+            v . . . . . v
+            v . . . . . v
+            > > 0 . 1 < <
+            . . . 2 . . .
+            . . . ^ . . .
+            . . . ^ . . ."#,
+        )
+        .unwrap();
+        game.step(&[Right, Left, Up]);
+        info!("{game:?}");
+        assert_eq!(game.outcome(), Outcome::Match);
+        assert!(game.grid.cells.iter().all(|c| c.t == CellT::Free));
+
+        // The order of the snakes does not matter
+        let mut game = Game::parse(
+            r#"
+            . . . . . . .
+            v . . . . . v
+            v . . . . . v
+            > > 2 . 1 < <
+            . . . 0 . . .
+            . . . ^ . . .
+            . . . ^ . . ."#,
+        )
+        .unwrap();
+        game.step(&[Up, Left, Right]);
+        info!("{game:?}");
+        assert_eq!(game.outcome(), Outcome::Match);
+    }
+
+    #[test]
+    fn game_step_head_to_head_four() {
+        use super::*;
+        use Direction::*;
+        logging();
+
+        // The longest snake survives
+        let mut game = Game::parse(
+            r#"
+            . . . v . . .
+            v . . v . . .
+            v . . 3 . . v
+            > > 0 . 1 < <
+            . . . 2 . . .
+            . . . ^ . . .
+            . . . ^ . . ."#,
+        )
+        .unwrap();
+        game.step(&[Right, Left, Up, Down]);
+        info!("{game:?}");
+        assert_eq!(game.outcome(), Outcome::Winner(0));
+        assert_eq!(game.snakes[0].head(), Vec2D::new(3, 3));
+        assert_eq!(game.snakes[0].body.len(), 5);
+
+        // All snakes are equally long and the food is gone
+        let mut game = Game::parse(
+            r#"
+            . . . v . . .
+            . . . v . . .
+            . . . 3 . . .
+            . > 0 o 1 < .
+            . . . 2 . . .
+            . . . ^ . . .
+            . . . . . . ."#,
+        )
+        .unwrap();
+        game.step(&[Right, Left, Up, Down]);
+        info!("{game:?}");
+        assert_eq!(game.outcome(), Outcome::Match);
+        assert!(game.grid.cells.iter().all(|c| c.t == CellT::Free));
+
+        // Two pairs of equally long snakes
+        let mut game = Game::parse(
+            r#"
+            . . . v . . .
+            v . . v . . .
+            v . . 3 . . v
+            > > 0 . 1 < <
+            . . . 2 . . .
+            . . . ^ . . .
+            . . . ^ . . ."#,
+        )
+        .unwrap();
+        game.snakes[3].body.push_front(Vec2D::new(3, 6));
+        game.snakes[3].body.push_front(Vec2D::new(3, 6));
+        game.step(&[Right, Left, Up, Down]);
+        info!("{game:?}");
+        assert_eq!(game.outcome(), Outcome::Match);
+    }
+
+    #[test]
+    fn game_step_body_collision() {
+        use super::*;
+        use Direction::*;
+        logging();
+
+        // Snake 0 runs into the body of 1, which dies in a head to head with 2
+        let mut game = Game::parse(
+            r#"
+            . . . . . . .
+            . . . . . . .
+            . . 0 . . . .
+            > > 1 . 2 < <
+            . . . . . . .
+            . . . . . . .
+            . . . . . . ."#,
+        )
+        .unwrap();
+        game.step(&[Down, Right, Left]);
+        info!("{game:?}");
+        assert_eq!(game.outcome(), Outcome::Match);
+        assert!(game.grid.cells.iter().all(|c| c.t == CellT::Free));
+
+        // Snake 0 runs into the body of 1, which starves
+        let mut game = Game::parse(
+            r#"
+            . . . . . . .
+            . . . . . . .
+            . . 0 . . . .
+            > > 1 . . . .
+            . . . . . . .
+            . . . . . . .
+            . . . . . . ."#,
+        )
+        .unwrap();
+        game.snakes[1].health = 1;
+        game.step(&[Down, Right]);
+        info!("{game:?}");
+        assert_eq!(game.outcome(), Outcome::Winner(0));
+        assert!(game.grid[Vec2D::new(2, 3)].t == CellT::Owned);
+        assert!(game.grid[Vec2D::new(3, 3)].t == CellT::Free);
+
+        // Snake 1 starves on a head to head with 0
+        let mut game = Game::parse(
+            r#"
+            . . . . . . .
+            . . . . . . .
+            . . . . . . .
+            . 0 . 1 < < <
+            . ^ . . . . .
+            . ^ . . . . .
+            . . . . . . ."#,
+        )
+        .unwrap();
+        game.snakes[1].health = 1;
+        game.step(&[Right, Left]);
+        info!("{game:?}");
+        assert_eq!(game.outcome(), Outcome::Winner(0));
+    }
+
     #[test]
     fn test_valid_moves() {
         use super::*;
@@ -630,4 +1161,418 @@ mod test {
         info!("{game:?}");
         assert!([Right].iter().cloned().eq(game.valid_moves(0)));
     }
+
+    #[test]
+    fn wrapped_step() {
+        use super::*;
+        use Direction::*;
+        logging();
+
+        let mut game = Game::parse(
+            r#"
+            . . . . . . . . . . .
+            . . . . . . . . . . .
+            . . . . . . . . . . .
+            . . . . . . . . . . .
+            . . . . . . . . . . .
+            0 < < . . . . . . . .
+            . . . . . . . . . . 1
+            . . . . . . . . . . ^
+            . . . . . . . . . . ^
+            . . . . . . . . . . .
+            . . . . . . . . . . ."#,
+        )
+        .unwrap();
+
+        assert!([Up, Down].iter().cloned().eq(game.valid_moves(0)));
+
+        game.set_mode(Mode::Wrapped);
+        assert!([Up, Down, Left].iter().cloned().eq(game.valid_moves(0)));
+        assert!([Up, Right, Left].iter().cloned().eq(game.valid_moves(1)));
+
+        {
+            // Head to head over the edge
+            let mut game = game.clone();
+            game.step(&[Left, Up]);
+            info!("{game:?}");
+            assert!(!game.snake_is_alive(0));
+            assert!(!game.snake_is_alive(1));
+        }
+
+        game.step(&[Left, Left]);
+        info!("{game:?}");
+        assert!(game.snake_is_alive(0));
+        assert!(game.snake_is_alive(1));
+        assert_eq!(game.snakes[0].head(), Vec2D::new(10, 5));
+        assert_eq!(game.snakes[1].head(), Vec2D::new(9, 4));
+
+        // Snake 0 runs into its own body over the edge
+        game.step(&[Right, Up]);
+        info!("{game:?}");
+        assert!(!game.snake_is_alive(0));
+        assert!(game.snake_is_alive(1));
+    }
+
+    #[test]
+    fn request_settings() {
+        use super::*;
+        logging();
+
+        let request: GameRequest = serde_json::from_str(
+            r#"{"game":{"id":"a","ruleset":{"name":"royale","version":"v1.1.20","settings":{"foodSpawnChance":25,"minimumFood":2,"hazardDamagePerTurn":30,"royale":{"shrinkEveryNTurns":10},"squad":{"allowBodyCollisions":false}}},"timeout":500},"turn":3,"board":{"width":11,"height":11,"food":[],"hazards":[{"x":5,"y":6}],"snakes":[{"id":"y","name":"y","health":90,"body":[{"x":5,"y":5},{"x":5,"y":4},{"x":5,"y":3}]}]},"you":{"id":"y","name":"y","health":90,"body":[{"x":5,"y":5},{"x":5,"y":4},{"x":5,"y":3}]}}"#,
+        )
+        .unwrap();
+        let settings = request.game.ruleset.settings;
+        assert_eq!(settings.food_spawn_chance, 25);
+        assert_eq!(settings.minimum_food, 2);
+        assert_eq!(settings.royale.shrink_every_n_turns, 10);
+        assert!(!settings.squad.allow_body_collisions);
+        assert!(settings.squad.shared_elimination);
+
+        let mut game = Game::from_request(&request);
+        assert_eq!(game.settings, settings);
+        game.step(&[Direction::Up]);
+        assert_eq!(game.snakes[0].health, 90 - 1 - 30);
+
+        // Missing settings fall back to the defaults
+        let request: GameRequest = serde_json::from_str(
+            r#"{"game":{"id":"a","ruleset":{"name":"standard"},"timeout":500},"turn":3,"board":{"width":11,"height":11,"food":[],"hazards":[],"snakes":[]},"you":{"id":"y","name":"y","health":90,"body":[{"x":5,"y":5}]}}"#,
+        )
+        .unwrap();
+        assert_eq!(request.game.ruleset.settings, RulesetSettings::default());
+        assert_eq!(request.game.ruleset.settings.hazard_damage_per_turn, 14);
+    }
+
+    #[test]
+    fn freeze_distant() {
+        use super::*;
+        use Direction::*;
+        logging();
+
+        let mut game = Game::parse(
+            r#"
+            5 . . . . . . . . . 4
+            ^ . . . . . . . . . ^
+            ^ . . . . . . . . . ^
+            . . . . . . . . . . .
+            . . . . . . . . . . .
+            . . . . 1 . 0 . . . .
+            . . . . ^ . ^ . . . .
+            . . . 3 ^ . ^ . 2 . .
+            . . . ^ . . . . ^ . .
+            . . . ^ . . . . ^ . .
+            . . . . . . . . . . ."#,
+        )
+        .unwrap();
+        assert_eq!(game.snakes.len(), 6);
+
+        game.freeze_distant(4);
+        info!("{game:?}");
+        assert_eq!(game.snakes.len(), 4);
+        assert_eq!(game.frozen.len(), 2);
+        assert_eq!(game.snakes[0].head(), Vec2D::new(6, 5));
+        assert!(game.snakes.iter().all(|s| s.head().y < 10));
+        assert!(game.frozen.iter().all(|s| s.head().y == 10));
+
+        // Frozen snakes do not move and remain as obstacles
+        game.step(&[Up, Up, Up, Up]);
+        assert!(game.grid[Vec2D::new(0, 8)].t == CellT::Owned);
+        assert!(game.grid[Vec2D::new(10, 8)].t == CellT::Owned);
+        assert!(game
+            .frozen
+            .iter()
+            .all(|s| s.body.len() == 3 && s.head().y == 10));
+
+        // The game is not over as long as frozen snakes are alive
+        for snake in &mut game.snakes[1..] {
+            snake.health = 1;
+        }
+        game.step(&[Up, Up, Up, Up]);
+        info!("{game:?}");
+        assert!(game.snake_is_alive(0));
+        assert_eq!(game.outcome(), Outcome::None);
+    }
+
+    #[test]
+    fn request_many_snakes() {
+        use super::*;
+        logging();
+
+        let snake = |id: usize| {
+            format!(
+                r#"{{"id":"{id}","name":"{id}","health":100,"body":[{{"x":{id},"y":0}},{{"x":{id},"y":1}},{{"x":{id},"y":2}}]}}"#
+            )
+        };
+        let snakes = (0..8).map(snake).collect::<Vec<_>>().join(",");
+        let request: GameRequest = serde_json::from_str(&format!(
+            r#"{{"game":{{"id":"a","ruleset":{{"name":"royale"}},"timeout":500}},"turn":0,"board":{{"width":11,"height":11,"food":[],"hazards":[],"snakes":[{snakes}]}},"you":{}}}"#,
+            snake(5)
+        ))
+        .unwrap();
+
+        let game = Game::from_request(&request);
+        info!("{game:?}");
+        assert_eq!(game.snakes.len(), 8);
+        assert_eq!(game.snakes[0].head(), Vec2D::new(5, 0));
+    }
+
+    #[test]
+    fn constrictor() {
+        use super::*;
+        use Direction::*;
+        logging();
+
+        let mut game = Game::parse(
+            r#"
+            . . . . . .
+            . > > 0 . .
+            . . . . . ."#,
+        )
+        .unwrap();
+        game.set_mode(Mode::Constrictor);
+        game.snakes[0].health = 50;
+
+        // Constrictor snakes grow every turn and keep their health
+        game.step(&[Right]);
+        info!("{game:?}");
+        assert_eq!(game.snakes[0].body.len(), 4);
+        assert_eq!(game.snakes[0].health, 100);
+        assert_eq!(game.snakes[0].body[0], game.snakes[0].body[1]);
+
+        // The stacked tail does not move anymore
+        game.step(&[Right]);
+        info!("{game:?}");
+        assert!(game.snake_is_alive(0));
+        assert_eq!(game.snakes[0].body.len(), 5);
+        assert!(game.grid[Vec2D::new(2, 1)].t == CellT::Owned);
+    }
+
+    #[test]
+    fn stacked_hazards() {
+        use super::*;
+        logging();
+
+        let request: GameRequest = serde_json::from_str(
+            r#"{"game":{"id":"a","ruleset":{"name":"standard","settings":{"hazardDamagePerTurn":14}},"timeout":500},"turn":3,"board":{"width":11,"height":11,"food":[],"hazards":[{"x":5,"y":6},{"x":5,"y":6},{"x":4,"y":5}],"snakes":[{"id":"y","name":"y","health":90,"body":[{"x":5,"y":5},{"x":5,"y":4},{"x":5,"y":3}]}]},"you":{"id":"y","name":"y","health":90,"body":[{"x":5,"y":5},{"x":5,"y":4},{"x":5,"y":3}]}}"#,
+        )
+        .unwrap();
+
+        let mut game = Game::from_request(&request);
+        assert_eq!(game.grid[Vec2D::new(5, 6)].hazards, 2);
+        assert_eq!(game.grid[Vec2D::new(4, 5)].hazards, 1);
+
+        let mut next = game.clone();
+        next.step(&[Direction::Left]);
+        assert_eq!(next.snakes[0].health, 90 - 1 - 14);
+
+        game.step(&[Direction::Up]);
+        info!("{game:?}");
+        assert_eq!(game.snakes[0].health, 90 - 1 - 2 * 14);
+    }
+
+    #[test]
+    fn squad() {
+        use super::*;
+        use Direction::*;
+        logging();
+
+        let mut game = Game::parse(
+            r#"
+            . . . . . . .
+            . 0 . . . . .
+            . ^ 1 . 2 < <
+            . ^ ^ . . . .
+            . . ^ . . . .
+            . . . . . . ."#,
+        )
+        .unwrap();
+        game.set_mode(Mode::Squad);
+        game.snakes[0].squad = Some(0);
+        game.snakes[1].squad = Some(0);
+        game.snakes[2].squad = Some(1);
+        game.snakes[0].health = 50;
+
+        // Squad members can move over each other
+        assert!(game.move_is_valid(1, Left));
+        game.step(&[Up, Left, Down]);
+        info!("{game:?}");
+        assert!(game.snake_is_alive(0));
+        assert!(game.snake_is_alive(1));
+        assert_eq!(game.snakes[1].head(), Vec2D::new(1, 3));
+        assert!(game.grid[Vec2D::new(1, 3)].t == CellT::Owned);
+        // Shared health
+        assert_eq!(game.snakes[0].health, 99);
+        assert_eq!(game.outcome(), Outcome::None);
+
+        // The last living squad wins
+        let mut won = game.clone();
+        won.snakes[2].health = 0;
+        assert_eq!(won.outcome(), Outcome::Winner(0));
+
+        // Shared elimination
+        game.step(&[Left, Right, Left]);
+        info!("{game:?}");
+        assert!(!game.snake_is_alive(0));
+        assert!(!game.snake_is_alive(1));
+        assert!(game.snake_is_alive(2));
+        assert_eq!(game.outcome(), Outcome::Winner(2));
+    }
+
+    #[test]
+    fn request_squads() {
+        use super::*;
+        logging();
+
+        let snake = |id: usize, squad: &str| {
+            format!(
+                r#"{{"id":"{id}","name":"{id}","health":100,"body":[{{"x":{id},"y":0}},{{"x":{id},"y":1}}],"squad":"{squad}"}}"#
+            )
+        };
+        let request: GameRequest = serde_json::from_str(&format!(
+            r#"{{"game":{{"id":"a","ruleset":{{"name":"squad"}},"timeout":500}},"turn":0,"board":{{"width":11,"height":11,"food":[],"hazards":[],"snakes":[{},{},{},{}]}},"you":{}}}"#,
+            snake(0, "red"),
+            snake(1, "blue"),
+            snake(2, "red"),
+            snake(3, "blue"),
+            snake(1, "blue"),
+        ))
+        .unwrap();
+
+        let game = Game::from_request(&request);
+        assert_eq!(game.mode, Mode::Squad);
+        let squads = game.snakes.iter().map(|s| s.squad).collect::<Vec<_>>();
+        assert_eq!(squads, [Some(0), Some(1), Some(1), Some(0)]);
+        assert!(game.snakes[0].allied(&game.snakes[3]));
+        assert!(!game.snakes[0].allied(&game.snakes[1]));
+    }
+
+    #[test]
+    fn hash_collisions() {
+        use super::*;
+        use Direction::*;
+        logging();
+
+        let game = |bodies: &[&[(i16, i16)]], food: &[Vec2D]| {
+            let snakes = bodies
+                .iter()
+                .map(|b| Snake::new(b.iter().map(|&(x, y)| Vec2D::new(x, y)).collect(), 100))
+                .collect();
+            Game::new(0, Mode::Standard, 11, 11, snakes, food, &[])
+        };
+
+        // Stacked tails at different cells (tail to head)
+        let a = game(&[&[(1, 1), (1, 1), (1, 2), (1, 3)]], &[]);
+        let b = game(&[&[(2, 2), (2, 2), (1, 2), (1, 3)]], &[]);
+        assert_ne!(a.hash(), b.hash());
+
+        // Number of stacked segments
+        let c = game(&[&[(1, 2), (1, 2), (1, 2), (1, 3)]], &[]);
+        let d = game(&[&[(1, 1), (1, 2), (1, 2), (1, 3)]], &[]);
+        assert_ne!(c.hash(), d.hash());
+
+        // Same cells in a different order
+        let e = game(&[&[(1, 1), (2, 1), (2, 2), (1, 2), (1, 3)]], &[]);
+        let f = game(&[&[(2, 1), (1, 1), (2, 2), (1, 2), (1, 3)]], &[]);
+        let g = game(&[&[(2, 2), (2, 1), (1, 1), (1, 2), (1, 3)]], &[]);
+        assert_ne!(e.hash(), f.hash());
+        assert_ne!(e.hash(), g.hash());
+
+        // Eating stacks the tail, which has to differ from the same move at another tail
+        let mut a = game(&[&[(1, 0), (1, 1), (1, 2)]], &[Vec2D::new(1, 3)]);
+        let mut b = game(&[&[(2, 1), (2, 2), (1, 2)]], &[Vec2D::new(1, 3)]);
+        let _ = a.apply(&[Up]);
+        let _ = b.apply(&[Up]);
+        assert_eq!(
+            a.snakes[0].body,
+            [(1, 1), (1, 1), (1, 2), (1, 3)].map(|(x, y)| Vec2D::new(x, y))
+        );
+        assert_ne!(a.hash(), b.hash());
+        let mut rehashed = a.clone();
+        rehashed.rehash();
+        assert_eq!(a.hash(), rehashed.hash());
+    }
+
+    #[test]
+    fn apply_undo() {
+        use super::*;
+        use rand::{rngs::SmallRng, seq::IteratorRandom, Rng, SeedableRng};
+        logging();
+
+        let mut rng = SmallRng::seed_from_u64(42);
+        for mode in [
+            Mode::Standard,
+            Mode::Wrapped,
+            Mode::Constrictor,
+            Mode::Squad,
+        ] {
+            for _ in 0..20 {
+                let mut game = Game::parse(
+                    r#"
+                    . . . . o . . . . . .
+                    . 0 . . . . . . . 1 .
+                    . ^ . . . . o . . ^ .
+                    . ^ . o . . . . . ^ .
+                    . . . . . . . . . . .
+                    o . . . . o . . . . o
+                    . . . . . . . . . . .
+                    . 2 . . . . . . o 3 .
+                    . ^ . . o . . . . ^ .
+                    . ^ . . . . . . . ^ .
+                    . . . . . o . . . . ."#,
+                )
+                .unwrap();
+                game.set_mode(mode);
+                for (i, snake) in game.snakes.iter_mut().enumerate() {
+                    snake.squad = Some(i as u8 / 2);
+                    snake.health = rng.gen_range(1..=100);
+                }
+                game.grid.add_hazards(&[Vec2D::new(1, 8), Vec2D::new(5, 5)]);
+                game.rehash();
+
+                let mut history = Vec::new();
+                while game.outcome() == Outcome::None && game.turn < 100 {
+                    let moves = (0..game.snakes.len() as u8)
+                        .map(|i| {
+                            // Mostly valid, sometimes deadly moves
+                            match game.valid_moves(i).choose(&mut rng) {
+                                Some(d) if rng.gen_range(0..10) > 0 => d,
+                                _ => Direction::from(rng.gen_range(0..4)),
+                            }
+                        })
+                        .collect::<Vec<_>>();
+
+                    let mut stepped = game.clone();
+                    stepped.step(&moves);
+
+                    let before = game.clone();
+                    let undo = game.apply(&moves);
+                    assert_same(&game, &stepped);
+                    history.push((before, undo));
+                }
+
+                // Revert all moves in reverse order
+                while let Some((before, undo)) = history.pop() {
+                    game.undo(undo);
+                    assert_same(&game, &before);
+                }
+            }
+        }
+
+        fn assert_same(game: &Game, expected: &Game) {
+            assert_eq!(game.turn, expected.turn);
+            // The incremental hash matches a full recomputation
+            let mut rehashed = game.clone();
+            rehashed.rehash();
+            assert_eq!(game.hash(), rehashed.hash());
+            assert_eq!(game.hash(), expected.hash());
+            assert!(
+                game.grid.cells == expected.grid.cells,
+                "{game:?} {expected:?}"
+            );
+            for (snake, expected) in game.snakes.iter().zip(&expected.snakes) {
+                assert_eq!(snake.health, expected.health);
+                assert_eq!(snake.body, expected.body);
+            }
+        }
+    }
 }
@@ -1,8 +1,8 @@
 use std::cmp::Reverse;
 use std::collections::{BinaryHeap, HashMap};
+use std::f64;
 use std::mem::size_of;
 use std::ops::{Index, IndexMut};
-use std::{f64, usize};
 
 use crate::env::{Direction, Vec2D, HAZARD_DAMAGE};
 use crate::util::OrdPair;
@@ -19,13 +19,14 @@ pub enum CellT {
 #[derive(Clone, Copy, PartialEq, Eq)]
 pub struct Cell {
     pub t: CellT,
-    pub hazard: bool,
+    /// Number of stacked hazards on this cell.
+    pub hazards: u8,
 }
 const _: () = assert!(size_of::<Cell>() == 2);
 
 impl Cell {
-    pub const fn new(t: CellT, hazard: bool) -> Self {
-        Self { t, hazard }
+    pub const fn new(t: CellT, hazards: u8) -> Self {
+        Self { t, hazards }
     }
 }
 
@@ -33,7 +34,7 @@ impl std::fmt::Debug for Cell {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         use owo_colors::{OwoColorize, Style};
 
-        let style = if self.hazard {
+        let style = if self.hazards > 0 {
             Style::new().on_bright_black()
         } else {
             Style::new()
@@ -55,6 +56,10 @@ impl std::fmt::Debug for Cell {
 pub struct Grid {
     pub width: usize,
     pub height: usize,
+    /// Moving over an edge leads to the opposite side of the board.
+    pub wrapped: bool,
+    /// Additional health that is lost when moving onto a hazard (`hazardDamagePerTurn`).
+    pub hazard_damage: u8,
     pub cells: Vec<Cell>,
 }
 
@@ -65,7 +70,9 @@ impl Grid {
         Self {
             width,
             height,
-            cells: vec![Cell::new(CellT::Free, false); width * height],
+            wrapped: false,
+            hazard_damage: HAZARD_DAMAGE,
+            cells: vec![Cell::new(CellT::Free, 0); width * height],
         }
     }
 
@@ -79,6 +86,8 @@ impl Grid {
         Self {
             width,
             height,
+            wrapped: false,
+            hazard_damage: HAZARD_DAMAGE,
             cells,
         }
     }
@@ -86,7 +95,7 @@ impl Grid {
     /// Clears the grid.
     pub fn clear(&mut self) {
         for c in &mut self.cells {
-            *c = Cell::new(CellT::Food, false);
+            *c = Cell::new(CellT::Food, 0);
         }
     }
 
@@ -99,6 +108,15 @@ impl Grid {
         }
     }
 
+    /// Removes the snakes from the grid.
+    pub fn remove_snake(&mut self, body: impl Iterator<Item = Vec2D>) {
+        for p in body {
+            if self.has(p) {
+                self[p].t = CellT::Free;
+            }
+        }
+    }
+
     /// Adds the provided food to the grid.
     pub fn add_food(&mut self, food: &[Vec2D]) {
         for &p in food {
@@ -109,17 +127,26 @@ impl Grid {
     }
 
     /// Adds the provided hazards to the grid.
+    /// Duplicates are stacked on the same cell.
     pub fn add_hazards(&mut self, hazards: &[Vec2D]) {
         for &p in hazards {
             if self.has(p) {
-                self[p].hazard = true;
+                self[p].hazards = self[p].hazards.saturating_add(1);
             }
         }
     }
 
     /// Returns if the cell is hazardous.
     pub fn is_hazardous(&self, p: Vec2D) -> bool {
-        self.has(p) && self[p].hazard
+        self.has(p) && self[p].hazards > 0
+    }
+
+    /// Returns the health that is lost when moving onto `p` without food.
+    pub fn damage(&self, p: Vec2D) -> u8 {
+        match self[p].hazards {
+            0 => 1,
+            n => self.hazard_damage.saturating_mul(n).saturating_add(1),
+        }
     }
 
     /// Returns if `p` is within the boundaries of this grid.
@@ -128,6 +155,30 @@ impl Grid {
         p.within(self.width, self.height)
     }
 
+    /// Returns the neighbor of `p` in direction `d` if it is on the board.
+    /// On wrapped boards the neighbor at the edge is on the opposite side.
+    #[inline]
+    pub fn neighbor(&self, p: Vec2D, d: Direction) -> Option<Vec2D> {
+        let p = p.apply(d);
+        if self.wrapped {
+            Some(Vec2D::new(
+                p.x.rem_euclid(self.width as _),
+                p.y.rem_euclid(self.height as _),
+            ))
+        } else if self.has(p) {
+            Some(p)
+        } else {
+            None
+        }
+    }
+
+    /// Returns the direction that leads from `p` to its neighbor `next`.
+    pub fn direction(&self, p: Vec2D, next: Vec2D) -> Direction {
+        Direction::iter()
+            .find(|&d| self.neighbor(p, d) == Some(next))
+            .unwrap_or_else(|| Direction::from(next - p))
+    }
+
     /// Performes an A* search that applies the `first_move_heuristic` as
     /// additional costs for the first move.
     #[must_use]
@@ -161,17 +212,20 @@ impl Grid {
             }
 
             for d in Direction::iter() {
-                let neighbor = front.apply(d);
+                let neighbor = match self.neighbor(front, d) {
+                    Some(neighbor) => neighbor,
+                    None => continue,
+                };
                 let mut neighbor_cost = cost + 1.0;
                 if self.is_hazardous(neighbor) {
-                    neighbor_cost += HAZARD_DAMAGE as f64;
+                    neighbor_cost += self.damage(neighbor) as f64;
                 }
                 if front == start {
                     neighbor_cost += first_move_heuristic[d as usize];
                 }
 
-                if self.has(neighbor) && self[neighbor].t != CellT::Owned {
-                    let cost_so_far = data.get(&neighbor).map_or(f64::MAX,  |(_, c)| *c);
+                if self[neighbor].t != CellT::Owned {
+                    let cost_so_far = data.get(&neighbor).map_or(f64::MAX, |(_, c)| *c);
                     if neighbor_cost < cost_so_far {
                         data.insert(neighbor, (front, neighbor_cost));
                         // queue does not accept float
@@ -192,7 +246,7 @@ impl Index<Vec2D> for Grid {
     fn index(&self, p: Vec2D) -> &Self::Output {
         assert!(0 <= p.x && p.x < self.width as _);
         assert!(0 <= p.y && p.y < self.height as _);
-        &self.cells[(p.x as usize + p.y as usize * self.width) as usize]
+        &self.cells[p.x as usize + p.y as usize * self.width]
     }
 }
 
@@ -200,7 +254,7 @@ impl IndexMut<Vec2D> for Grid {
     fn index_mut(&mut self, p: Vec2D) -> &mut Self::Output {
         assert!(0 <= p.x && p.x < self.width as _);
         assert!(0 <= p.y && p.y < self.height as _);
-        &mut self.cells[(p.x as usize + p.y as usize * self.width) as usize]
+        &mut self.cells[p.x as usize + p.y as usize * self.width]
     }
 }
 
@@ -258,4 +312,42 @@ mod test {
         assert_eq!(path[0], Vec2D::new(0, 2));
         assert_eq!(path[path.len() - 1], Vec2D::new(4, 2));
     }
+
+    #[test]
+    fn grid_a_star_stacked_hazards() {
+        use super::*;
+        logging();
+        let mut grid = Grid::new(5, 5);
+        grid.add_hazards(&(0..5).map(|y| Vec2D::new(2, y)).collect::<Vec<_>>());
+        grid.add_hazards(&[Vec2D::new(2, 2), Vec2D::new(2, 2)]);
+        assert_eq!(grid.damage(Vec2D::new(1, 2)), 1);
+        assert_eq!(grid.damage(Vec2D::new(2, 1)), 1 + HAZARD_DAMAGE);
+        assert_eq!(grid.damage(Vec2D::new(2, 2)), 1 + 3 * HAZARD_DAMAGE);
+
+        grid.hazard_damage = 100;
+        assert_eq!(grid.damage(Vec2D::new(2, 2)), u8::MAX);
+
+        // Detour around the stacked hazard
+        let path = grid
+            .a_star(Vec2D::new(0, 2), Vec2D::new(4, 2), &[1.0, 1.0, 1.0, 1.0])
+            .unwrap();
+        info!("{:?}", path);
+        assert_eq!(path.len(), 7);
+        assert!(!path.contains(&Vec2D::new(2, 2)));
+    }
+
+    #[test]
+    fn grid_a_star_wrapped() {
+        use super::*;
+        logging();
+        let mut grid = Grid::new(11, 11);
+        grid.wrapped = true;
+
+        let path = grid
+            .a_star(Vec2D::new(0, 5), Vec2D::new(10, 5), &[0.0, 0.0, 0.0, 0.0])
+            .unwrap();
+        info!("{:?}", path);
+        assert_eq!(path, vec![Vec2D::new(0, 5), Vec2D::new(10, 5)]);
+        assert_eq!(grid.direction(path[0], path[1]), Direction::Left);
+    }
 }
@@ -0,0 +1,197 @@
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+use crate::env::GameRequest;
+
+/// Number of previous turns that are used for the estimate.
+const WINDOW: usize = 8;
+
+/// Time that is reserved for the latency between the game engine and the server.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LatencyConfig {
+    /// Estimate the latency of each game, instead of always using the `fallback`
+    pub adaptive: bool,
+    /// Reserved time in ms, if there is no estimate yet
+    pub fallback: u64,
+    /// Time in ms that is added to the estimate
+    pub margin: u64,
+    /// Minimal reserved time in ms
+    pub floor: u64,
+}
+
+impl LatencyConfig {
+    /// Always reserves the same time in ms.
+    pub fn fixed(latency: u64) -> Self {
+        Self {
+            adaptive: false,
+            fallback: latency,
+            margin: 0,
+            floor: 0,
+        }
+    }
+}
+
+/// Estimates the latency of a game from the timing of the previous turns.
+#[derive(Debug, Default, Clone)]
+pub struct LatencyEstimator {
+    /// Latency in ms that was reported by the game engine, without our response time
+    reported: VecDeque<u64>,
+    /// Time in ms between the requests that we did not spend on the responses
+    gaps: VecDeque<u64>,
+    /// Arrival of the current request
+    arrival: Option<Instant>,
+    /// Arrival of the previous request and our response time
+    previous: Option<(Instant, Duration)>,
+}
+
+impl LatencyEstimator {
+    /// Records the arrival of a move request.
+    pub fn arrive(&mut self, request: &GameRequest, now: Instant) {
+        if let Some((arrival, time)) = self.previous.take() {
+            // The engine measures the round trip time of our previous response
+            if let Ok(latency) = request.you.latency.parse::<u64>() {
+                if latency > 0 {
+                    push(
+                        &mut self.reported,
+                        latency.saturating_sub(time.as_millis() as u64),
+                    );
+                }
+            }
+            // The engine waits for all snakes, so this is only an upper bound
+            let gap = now.saturating_duration_since(arrival).saturating_sub(time);
+            push(&mut self.gaps, gap.as_millis() as u64);
+        }
+        self.arrival = Some(now);
+    }
+
+    /// Records our response time to the current request.
+    pub fn respond(&mut self, time: Duration) {
+        self.previous = self.arrival.take().map(|arrival| (arrival, time));
+    }
+
+    /// Estimated latency in ms, if there are previous turns.
+    ///
+    /// This is the maximum of the reported latencies or,
+    /// if the engine reported none, the minimal time between the requests.
+    pub fn estimate(&self) -> Option<u64> {
+        self.reported
+            .iter()
+            .max()
+            .or_else(|| self.gaps.iter().min())
+            .copied()
+    }
+
+    /// Time in ms that is reserved for the latency of the next response.
+    pub fn latency(&self, config: &LatencyConfig) -> u64 {
+        if !config.adaptive {
+            return config.fallback;
+        }
+        match self.estimate() {
+            Some(latency) if self.reported.is_empty() => {
+                // The time between the requests can only lower the fallback
+                (latency + config.margin)
+                    .min(config.fallback)
+                    .max(config.floor)
+            }
+            Some(latency) => (latency + config.margin).max(config.floor),
+            None => config.fallback,
+        }
+    }
+}
+
+fn push(samples: &mut VecDeque<u64>, v: u64) {
+    if samples.len() >= WINDOW {
+        samples.pop_front();
+    }
+    samples.push_back(v);
+}
+
+#[cfg(test)]
+mod test {
+    use crate::logging;
+
+    fn request(latency: &str) -> super::GameRequest {
+        request_json(&format!(r#""{latency}""#))
+    }
+
+    /// Request with the `latency` as raw JSON value.
+    fn request_json(latency: &str) -> super::GameRequest {
+        let you = format!(
+            r#"{{"id":"a","name":"a","health":90,"body":[{{"x":1,"y":1}}],"latency":{latency}}}"#
+        );
+        serde_json::from_str(&format!(
+            r#"{{"game":{{"id":"g","ruleset":{{"name":"standard"}},"timeout":500}},"turn":0,"board":{{"width":11,"height":11,"food":[],"hazards":[],"snakes":[{you}]}},"you":{you}}}"#
+        ))
+        .unwrap()
+    }
+
+    #[test]
+    fn latency_estimate() {
+        use super::*;
+        logging();
+
+        let config = LatencyConfig {
+            adaptive: true,
+            fallback: 100,
+            margin: 10,
+            floor: 30,
+        };
+        let ms = Duration::from_millis;
+        let start = Instant::now();
+
+        let mut estimator = LatencyEstimator::default();
+        assert_eq!(estimator.estimate(), None);
+        assert_eq!(estimator.latency(&config), 100);
+        assert_eq!(estimator.latency(&LatencyConfig::fixed(50)), 50);
+
+        // The first turn has no reported latency
+        estimator.arrive(&request(""), start);
+        estimator.respond(ms(300));
+        assert_eq!(estimator.estimate(), None);
+
+        // Only the time between the requests
+        estimator.arrive(&request(""), start + ms(360));
+        estimator.respond(ms(300));
+        assert_eq!(estimator.estimate(), Some(60));
+        assert_eq!(estimator.latency(&config), 70);
+        estimator.arrive(&request(""), start + ms(1000));
+        estimator.respond(ms(300));
+        assert_eq!(estimator.estimate(), Some(60));
+
+        // Reported latencies are preferred
+        estimator.arrive(&request("340"), start + ms(1500));
+        estimator.respond(ms(200));
+        assert_eq!(estimator.estimate(), Some(40));
+        assert_eq!(estimator.latency(&config), 50);
+        estimator.arrive(&request("205"), start + ms(2000));
+        estimator.respond(ms(200));
+        assert_eq!(estimator.estimate(), Some(40));
+        assert_eq!(estimator.latency(&config), 50);
+        // Older engines report numbers
+        assert_eq!(request_json("205").you.latency, "205");
+        estimator.arrive(&request_json("205"), start + ms(2250));
+        estimator.respond(ms(200));
+        assert_eq!(estimator.estimate(), Some(40));
+
+        // Fast connections are limited by the floor
+        for i in 0..WINDOW as u32 {
+            estimator.arrive(&request("205"), start + ms(2500) + ms(500) * i);
+            estimator.respond(ms(200));
+        }
+        assert_eq!(estimator.estimate(), Some(5));
+        assert_eq!(estimator.latency(&config), 30);
+
+        // Large gaps do not exceed the fallback
+        let mut estimator = LatencyEstimator::default();
+        estimator.arrive(&request(""), start);
+        estimator.respond(ms(100));
+        estimator.arrive(&request(""), start + ms(900));
+        assert_eq!(estimator.estimate(), Some(800));
+        assert_eq!(estimator.latency(&config), 100);
+        // Including the margin
+        estimator.respond(ms(100));
+        estimator.arrive(&request(""), start + ms(1090));
+        assert_eq!(estimator.estimate(), Some(90));
+        assert_eq!(estimator.latency(&config), 100);
+    }
+}
@@ -4,13 +4,21 @@ use std::io::Write;
 
 // Exported to be accessable in benchmarks
 pub mod agents;
+pub mod bitboard;
 pub mod env;
 pub mod floodfill;
+pub mod food;
 pub mod game;
 pub mod grid;
-mod savegame;
-mod util;
+pub mod latency;
+pub mod metrics;
+pub mod rules;
+pub mod savegame;
 pub mod search;
+pub mod server;
+pub mod session;
+mod util;
+mod zobrist;
 
 pub fn logging() {
     #[cfg(not(test))]
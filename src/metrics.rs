@@ -0,0 +1,308 @@
+//! Metrics of the server in the Prometheus text format.
+//!
+//! See: https://prometheus.io/docs/instrumenting/exposition_formats/
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::sync::atomic::{AtomicI64, AtomicU64, Ordering};
+use std::sync::Mutex;
+
+use warp::Filter;
+
+/// Metrics that are collected by the agents and the server.
+pub static METRICS: Metrics = Metrics::new();
+
+/// Monotonically increasing value.
+#[derive(Debug, Default)]
+pub struct Counter(AtomicU64);
+
+impl Counter {
+    pub const fn new() -> Self {
+        Self(AtomicU64::new(0))
+    }
+
+    pub fn inc(&self) {
+        self.0.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn get(&self) -> u64 {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+/// Value that can go up and down.
+#[derive(Debug, Default)]
+pub struct Gauge(AtomicI64);
+
+impl Gauge {
+    pub const fn new() -> Self {
+        Self(AtomicI64::new(0))
+    }
+
+    pub fn set(&self, v: i64) {
+        self.0.store(v, Ordering::Relaxed);
+    }
+
+    pub fn get(&self) -> i64 {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+/// Counters that are distinguished by a label value.
+#[derive(Debug, Default)]
+pub struct CounterVec(Mutex<BTreeMap<String, u64>>);
+
+impl CounterVec {
+    pub const fn new() -> Self {
+        Self(Mutex::new(BTreeMap::new()))
+    }
+
+    pub fn inc(&self, label: &str) {
+        if let Ok(mut counters) = self.0.lock() {
+            *counters.entry(label.into()).or_default() += 1;
+        }
+    }
+
+    pub fn get(&self, label: &str) -> u64 {
+        self.0
+            .lock()
+            .ok()
+            .and_then(|c| c.get(label).copied())
+            .unwrap_or_default()
+    }
+}
+
+/// Cumulative histogram with fixed bucket bounds.
+#[derive(Debug)]
+pub struct Histogram<const N: usize> {
+    bounds: [f64; N],
+    buckets: [AtomicU64; N],
+    count: AtomicU64,
+    /// Bits of the `f64` sum
+    sum: AtomicU64,
+}
+
+impl<const N: usize> Histogram<N> {
+    /// Creates a histogram with the upper bounds of the buckets in increasing order.
+    pub const fn new(bounds: [f64; N]) -> Self {
+        Self {
+            bounds,
+            buckets: [const { AtomicU64::new(0) }; N],
+            count: AtomicU64::new(0),
+            sum: AtomicU64::new(0),
+        }
+    }
+
+    pub fn observe(&self, v: f64) {
+        if let Some(i) = self.bounds.iter().position(|&b| v <= b) {
+            self.buckets[i].fetch_add(1, Ordering::Relaxed);
+        }
+        self.count.fetch_add(1, Ordering::Relaxed);
+        let _ = self
+            .sum
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |s| {
+                Some((f64::from_bits(s) + v).to_bits())
+            });
+    }
+
+    pub fn count(&self) -> u64 {
+        self.count.load(Ordering::Relaxed)
+    }
+
+    pub fn sum(&self) -> f64 {
+        f64::from_bits(self.sum.load(Ordering::Relaxed))
+    }
+}
+
+/// All metrics of the server.
+#[derive(Debug)]
+pub struct Metrics {
+    /// Response time of the move requests in seconds
+    pub move_latency: Histogram<12>,
+    /// Moves that took longer than the game timeout
+    pub timeouts: Counter,
+    /// Deepest completed iteration of the tree searches
+    pub search_depth: Histogram<16>,
+    /// Moves that fell back to a random or the first valid move
+    pub fallbacks: Counter,
+    /// Games with a session on the server
+    pub active_games: Gauge,
+    /// Moves by agent variant
+    pub agent_moves: CounterVec,
+}
+
+impl Metrics {
+    pub const fn new() -> Self {
+        Self {
+            move_latency: Histogram::new([
+                0.005, 0.01, 0.025, 0.05, 0.1, 0.15, 0.2, 0.3, 0.4, 0.5, 0.75, 1.0,
+            ]),
+            timeouts: Counter::new(),
+            search_depth: Histogram::new([
+                1.0, 2.0, 3.0, 4.0, 5.0, 6.0, 7.0, 8.0, 9.0, 10.0, 11.0, 12.0, 13.0, 14.0, 15.0,
+                16.0,
+            ]),
+            fallbacks: Counter::new(),
+            active_games: Gauge::new(),
+            agent_moves: CounterVec::new(),
+        }
+    }
+
+    /// Returns the metrics in the Prometheus text format.
+    pub fn render(&self) -> String {
+        let mut out = String::new();
+        histogram(
+            &mut out,
+            "snork_move_latency_seconds",
+            "Response time of the move requests.",
+            &self.move_latency,
+        );
+        counter(
+            &mut out,
+            "snork_timeouts_total",
+            "Moves that took longer than the game timeout.",
+            self.timeouts.get(),
+        );
+        histogram(
+            &mut out,
+            "snork_search_depth",
+            "Deepest completed iteration of the tree searches.",
+            &self.search_depth,
+        );
+        counter(
+            &mut out,
+            "snork_fallbacks_total",
+            "Moves that fell back to a random or the first valid move.",
+            self.fallbacks.get(),
+        );
+
+        let _ = writeln!(
+            out,
+            "# HELP snork_active_games Games with a session on the server."
+        );
+        let _ = writeln!(out, "# TYPE snork_active_games gauge");
+        let _ = writeln!(out, "snork_active_games {}", self.active_games.get());
+
+        let _ = writeln!(
+            out,
+            "# HELP snork_agent_moves_total Moves by agent variant."
+        );
+        let _ = writeln!(out, "# TYPE snork_agent_moves_total counter");
+        if let Ok(counters) = self.agent_moves.0.lock() {
+            for (agent, v) in counters.iter() {
+                let _ = writeln!(out, "snork_agent_moves_total{{agent=\"{agent}\"}} {v}");
+            }
+        }
+        out
+    }
+}
+
+impl Default for Metrics {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+fn counter(out: &mut String, name: &str, help: &str, v: u64) {
+    let _ = writeln!(out, "# HELP {name} {help}");
+    let _ = writeln!(out, "# TYPE {name} counter");
+    let _ = writeln!(out, "{name} {v}");
+}
+
+fn histogram<const N: usize>(out: &mut String, name: &str, help: &str, h: &Histogram<N>) {
+    let _ = writeln!(out, "# HELP {name} {help}");
+    let _ = writeln!(out, "# TYPE {name} histogram");
+    let mut cumulative = 0;
+    for (bound, bucket) in h.bounds.iter().zip(&h.buckets) {
+        cumulative += bucket.load(Ordering::Relaxed);
+        let _ = writeln!(out, "{name}_bucket{{le=\"{bound}\"}} {cumulative}");
+    }
+    let _ = writeln!(out, "{name}_bucket{{le=\"+Inf\"}} {}", h.count());
+    let _ = writeln!(out, "{name}_sum {}", h.sum());
+    let _ = writeln!(out, "{name}_count {}", h.count());
+}
+
+/// `GET /metrics` route that serves the global metrics.
+pub fn route() -> impl Filter<Extract = (impl warp::Reply,), Error = warp::Rejection> + Clone {
+    warp::path("metrics")
+        .and(warp::path::end())
+        .and(warp::get())
+        .map(|| {
+            warp::reply::with_header(
+                METRICS.render(),
+                "content-type",
+                "text/plain; version=0.0.4",
+            )
+        })
+}
+
+#[cfg(test)]
+mod test {
+    use crate::logging;
+
+    #[test]
+    fn metrics_render() {
+        use super::*;
+        logging();
+
+        let metrics = Metrics::new();
+        for v in [0.003, 0.12, 0.12, 2.0] {
+            metrics.move_latency.observe(v);
+        }
+        metrics.timeouts.inc();
+        metrics.search_depth.observe(3.0);
+        metrics.active_games.set(2);
+        metrics.agent_moves.inc("Flood");
+        metrics.agent_moves.inc("Flood");
+        metrics.agent_moves.inc("Tree");
+
+        let out = metrics.render();
+        let lines = out.lines().collect::<Vec<_>>();
+        for expected in [
+            r#"snork_move_latency_seconds_bucket{le="0.005"} 1"#,
+            r#"snork_move_latency_seconds_bucket{le="0.1"} 1"#,
+            r#"snork_move_latency_seconds_bucket{le="0.15"} 3"#,
+            r#"snork_move_latency_seconds_bucket{le="1"} 3"#,
+            r#"snork_move_latency_seconds_bucket{le="+Inf"} 4"#,
+            "snork_move_latency_seconds_count 4",
+            "snork_timeouts_total 1",
+            r#"snork_search_depth_bucket{le="2"} 0"#,
+            r#"snork_search_depth_bucket{le="3"} 1"#,
+            "snork_search_depth_sum 3",
+            "snork_fallbacks_total 0",
+            "snork_active_games 2",
+            r#"snork_agent_moves_total{agent="Flood"} 2"#,
+            r#"snork_agent_moves_total{agent="Tree"} 1"#,
+        ] {
+            assert!(lines.contains(&expected), "missing {expected}:\n{out}");
+        }
+        assert!((metrics.move_latency.sum() - 2.243).abs() < 1e-9);
+    }
+
+    #[tokio::test]
+    async fn metrics_route() {
+        use super::*;
+        logging();
+
+        METRICS.agent_moves.inc("Test");
+        let response = warp::test::request()
+            .method("GET")
+            .path("/metrics")
+            .reply(&route())
+            .await;
+        assert_eq!(response.status(), 200);
+        assert_eq!(
+            response.headers()["content-type"],
+            "text/plain; version=0.0.4"
+        );
+        let body = String::from_utf8(response.body().to_vec()).unwrap();
+        assert!(body.contains("# TYPE snork_move_latency_seconds histogram"));
+        assert!(body.contains(r#"snork_agent_moves_total{agent="Test"} 1"#));
+
+        let response = warp::test::request()
+            .method("POST")
+            .path("/metrics")
+            .reply(&route())
+            .await;
+        assert_eq!(response.status(), 405);
+    }
+}
@@ -0,0 +1,363 @@
+use rand::seq::SliceRandom;
+use rand::{rngs::SmallRng, Rng, SeedableRng};
+
+use crate::env::{v2, RulesetSettings, Vec2D};
+use crate::grid::Grid;
+
+/// Maps that populate the board with hazards.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Map {
+    /// Hazards are not changed.
+    #[default]
+    Standard,
+    /// The safe area shrinks from a random side.
+    Royale,
+    /// A static ring of hazards two cells inside the border.
+    InnerWall,
+    /// Static concentric rings of hazards.
+    Rings,
+    /// Hazards spiral out from a random cell near the center.
+    Spiral,
+    /// Hazards are scattered over the board in random order.
+    Scatter,
+    /// A fixed maze of hazard walls on a 19x21 board,
+    /// where food only spawns on fixed cells.
+    ///
+    /// It is meant to be played with a hazard damage of 100, so that the walls are deadly
+    /// (see [`Map::settings`]).
+    ArcadeMaze,
+}
+
+/// Layout of the `arcade_maze` map from the top row (highest y) to the bottom,
+/// with hazards (`#`) and food spawns (`o`).
+const ARCADE_MAZE: [&str; 21] = [
+    "###################",
+    "#.................#",
+    "#.##.####.####.##.#",
+    "#...o.........o...#",
+    "#.##.#.#####.#.##.#",
+    "#....#...#...#....#",
+    "####.###.#.###.####",
+    "#.....#..o..#.....#",
+    "#.##..#.#.#.#..##.#",
+    "#o......#.#......o#",
+    "#.##.#.......#.##.#",
+    "#.....#.#o#.#.....#",
+    "#.##.##.....##.##.#",
+    "#....#.......#....#",
+    "#.##.#.#####.#.##.#",
+    "#...o.........o...#",
+    "###.#.###.###.#.###",
+    "#.....#..o..#.....#",
+    "#.##..#.#.#.#..##.#",
+    "#.................#",
+    "###################",
+];
+
+/// Cells of the `ARCADE_MAZE` layout that are marked with `c`.
+fn arcade_maze(c: char) -> impl Iterator<Item = Vec2D> {
+    let height = ARCADE_MAZE.len();
+    ARCADE_MAZE.iter().enumerate().flat_map(move |(row, line)| {
+        line.chars()
+            .enumerate()
+            .filter(move |&(_, cell)| cell == c)
+            .map(move |(x, _)| v2(x as _, (height - 1 - row) as _))
+    })
+}
+
+impl From<&str> for Map {
+    fn from(name: &str) -> Self {
+        match name {
+            "royale" => Self::Royale,
+            "hz_inner_wall" => Self::InnerWall,
+            "hz_rings" => Self::Rings,
+            "hz_spiral" => Self::Spiral,
+            "hz_scatter" => Self::Scatter,
+            "arcade_maze" => Self::ArcadeMaze,
+            _ => Self::Standard,
+        }
+    }
+}
+
+impl Map {
+    /// Board size (width, height) of maps that only support a single size.
+    pub fn size(&self) -> Option<(usize, usize)> {
+        match self {
+            Map::ArcadeMaze => Some((ARCADE_MAZE[0].len(), ARCADE_MAZE.len())),
+            _ => None,
+        }
+    }
+
+    /// Default ruleset settings of the map.
+    pub fn settings(&self) -> RulesetSettings {
+        match self {
+            Map::ArcadeMaze => RulesetSettings {
+                hazard_damage_per_turn: 100,
+                ..RulesetSettings::default()
+            },
+            _ => RulesetSettings::default(),
+        }
+    }
+
+    /// Cells on which food spawns, if the map has a fixed food layout.
+    ///
+    /// Otherwise, food is placed like in the standard ruleset.
+    pub fn food_spawns(&self, grid: &Grid) -> Option<Vec<Vec2D>> {
+        match self {
+            Map::ArcadeMaze => Some(arcade_maze('o').filter(|&p| grid.has(p)).collect()),
+            _ => None,
+        }
+    }
+
+    /// Updates the hazards of the map for the given turn.
+    ///
+    /// This is called at the start of the game (turn 0) and after every turn.
+    /// The hazards only depend on the turn, the settings, and the `seed`.
+    pub fn update(&self, grid: &mut Grid, turn: usize, settings: &RulesetSettings, seed: u64) {
+        let shrink_every_n_turns = settings.royale.shrink_every_n_turns;
+        match self {
+            Map::Standard => {}
+            Map::Royale => royale_hazards(grid, turn, shrink_every_n_turns, seed),
+            Map::InnerWall => set_hazards(grid, ring(grid, 2)),
+            Map::Rings => {
+                let size = grid.width.min(grid.height);
+                let rings = (2..)
+                    .step_by(2)
+                    .take_while(|inset| 2 * inset < size)
+                    .flat_map(|inset| ring(grid, inset))
+                    .collect::<Vec<_>>();
+                set_hazards(grid, rings);
+            }
+            Map::Spiral if shrink_every_n_turns > 0 => {
+                let hazards = spiral(grid, seed)
+                    .take(turn / shrink_every_n_turns)
+                    .collect::<Vec<_>>();
+                set_hazards(grid, hazards);
+            }
+            Map::Scatter if shrink_every_n_turns > 0 => {
+                let mut rng = SmallRng::seed_from_u64(seed);
+                let mut cells = (0..grid.height as i16)
+                    .flat_map(|y| (0..grid.width as i16).map(move |x| v2(x, y)))
+                    .collect::<Vec<_>>();
+                cells.shuffle(&mut rng);
+                cells.truncate(turn / shrink_every_n_turns);
+                set_hazards(grid, cells);
+            }
+            Map::Spiral | Map::Scatter => {}
+            Map::ArcadeMaze => {
+                let walls = arcade_maze('#')
+                    .filter(|&p| grid.has(p))
+                    .collect::<Vec<_>>();
+                set_hazards(grid, walls);
+            }
+        }
+    }
+}
+
+/// Replaces all hazards with the given cells.
+///
+/// Cells that are given multiple times are stacked.
+fn set_hazards(grid: &mut Grid, hazards: impl IntoIterator<Item = Vec2D>) {
+    for cell in &mut grid.cells {
+        cell.hazards = 0;
+    }
+    for p in hazards {
+        grid[p].hazards = grid[p].hazards.saturating_add(1);
+    }
+}
+
+/// Cells of the rectangle that is `inset` cells inside the border.
+fn ring(grid: &Grid, inset: usize) -> Vec<Vec2D> {
+    let (min_x, max_x) = (inset, grid.width.saturating_sub(inset + 1));
+    let (min_y, max_y) = (inset, grid.height.saturating_sub(inset + 1));
+    (min_y..=max_y)
+        .flat_map(|y| (min_x..=max_x).map(move |x| (x, y)))
+        .filter(|&(x, y)| x == min_x || x == max_x || y == min_y || y == max_y)
+        .map(|(x, y)| v2(x as _, y as _))
+        .collect()
+}
+
+/// Cells on the board, that spiral out from a random cell near the center.
+fn spiral(grid: &Grid, seed: u64) -> impl Iterator<Item = Vec2D> + '_ {
+    let mut rng = SmallRng::seed_from_u64(seed);
+    let (width, height) = (grid.width as i16, grid.height as i16);
+    let start = v2(
+        rng.gen_range(width / 3..width - width / 3),
+        rng.gen_range(height / 3..height - height / 3),
+    );
+
+    // Right 1, up 1, left 2, down 2, right 3, ...
+    let steps = [v2(1, 0), v2(0, 1), v2(-1, 0), v2(0, -1)];
+    let arms = 2 * width.max(height) as usize;
+    let moves = (0..2 * arms).flat_map(move |i| std::iter::repeat_n(steps[i % 4], i / 2 + 1));
+    std::iter::once(start)
+        .chain(moves.scan(start, |p, step| {
+            *p = *p + step;
+            Some(*p)
+        }))
+        .filter(|&p| grid.has(p))
+}
+
+/// Updates the hazards of the royale ruleset for the given turn.
+///
+/// Every `shrink_every_n_turns` one random side of the safe area shrinks,
+/// until only a single cell is left.
+/// Like the official ruleset, the sides are drawn from a rng with a fixed `seed`,
+/// so that the hazards only depend on the seed and the current turn.
+pub fn royale_hazards(grid: &mut Grid, turn: usize, shrink_every_n_turns: usize, seed: u64) {
+    if shrink_every_n_turns == 0 {
+        return;
+    }
+
+    let mut rng = SmallRng::seed_from_u64(seed);
+    let (mut min_x, mut max_x) = (0, grid.width - 1);
+    let (mut min_y, mut max_y) = (0, grid.height - 1);
+    for _ in 0..turn / shrink_every_n_turns {
+        match rng.gen_range(0..4) {
+            0 if min_x < max_x => min_x += 1,
+            1 if max_x > min_x => max_x -= 1,
+            2 if min_y < max_y => min_y += 1,
+            3 if max_y > min_y => max_y -= 1,
+            _ => {}
+        }
+    }
+
+    for y in 0..grid.height {
+        for x in 0..grid.width {
+            let hazard = x < min_x || x > max_x || y < min_y || y > max_y;
+            grid[v2(x as _, y as _)].hazards = hazard as u8;
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::logging;
+    use log::info;
+
+    #[test]
+    fn royale_shrinking() {
+        use super::*;
+        logging();
+
+        let mut grid = Grid::new(11, 11);
+        let hazards = |grid: &Grid| grid.cells.iter().filter(|c| c.hazards > 0).count();
+
+        // Nothing before the first shrink
+        royale_hazards(&mut grid, 24, 25, 42);
+        assert_eq!(hazards(&grid), 0);
+
+        // One side per shrink
+        royale_hazards(&mut grid, 25, 25, 42);
+        info!("{grid:?}");
+        assert_eq!(hazards(&grid), 11);
+
+        // Deterministic and growing with the turns
+        let mut last = grid.clone();
+        for turn in 26..1000 {
+            let mut next = Grid::new(11, 11);
+            royale_hazards(&mut next, turn, 25, 42);
+            assert!(last
+                .cells
+                .iter()
+                .zip(&next.cells)
+                .all(|(l, n)| l.hazards <= n.hazards));
+            last = next;
+        }
+
+        // The board is never completely covered
+        assert_eq!(hazards(&last), 11 * 11 - 1);
+    }
+
+    #[test]
+    fn maps() {
+        use super::*;
+        logging();
+
+        let settings = RulesetSettings::default();
+        let hazards = |map: Map, turn: usize| {
+            let mut grid = Grid::new(11, 11);
+            map.update(&mut grid, turn, &settings, 42);
+            info!("{map:?} {turn}: {grid:?}");
+            grid.cells.iter().filter(|c| c.hazards > 0).count()
+        };
+
+        assert_eq!(Map::from("hz_inner_wall"), Map::InnerWall);
+        assert_eq!(Map::from("unknown"), Map::Standard);
+
+        assert_eq!(hazards(Map::Standard, 100), 0);
+        assert_eq!(hazards(Map::InnerWall, 0), 24);
+        assert_eq!(hazards(Map::Rings, 0), 24 + 8);
+
+        // One hazard every 25 turns until the board is filled
+        for map in [Map::Spiral, Map::Scatter] {
+            assert_eq!(hazards(map, 24), 0);
+            assert_eq!(hazards(map, 25), 1);
+            assert_eq!(hazards(map, 260), 10);
+            assert_eq!(hazards(map, 10000), 11 * 11);
+        }
+
+        // The spiral grows around its start
+        let mut grid = Grid::new(11, 11);
+        Map::Spiral.update(&mut grid, 9 * 25, &settings, 42);
+        let start = spiral(&grid, 42).next().unwrap();
+        for y in -1..=1 {
+            for x in -1..=1 {
+                assert!(grid.is_hazardous(start + v2(x, y)));
+            }
+        }
+
+        // A fixed maze with connected corridors and food spawns
+        let maze = Map::from("arcade_maze");
+        assert_eq!(maze, Map::ArcadeMaze);
+        assert_eq!(maze.size(), Some((19, 21)));
+        assert_eq!(Map::Standard.settings(), settings);
+        let mut grid = Grid::new(19, 21);
+        grid.hazard_damage = maze.settings().hazard_damage_per_turn;
+        maze.update(&mut grid, 0, &settings, 42);
+        assert!(grid.damage(v2(0, 0)) > 100);
+        assert!(ARCADE_MAZE.iter().all(|l| l.len() == 19));
+        assert!(ARCADE_MAZE.iter().all(|l| l.chars().eq(l.chars().rev())));
+        let mut grid = Grid::new(19, 21);
+        maze.update(&mut grid, 0, &settings, 42);
+        let free = grid.cells.iter().filter(|c| c.hazards == 0).count();
+        let spawns = maze.food_spawns(&grid).unwrap();
+        assert_eq!(spawns.len(), 9);
+        assert!(spawns.iter().all(|&p| !grid.is_hazardous(p)));
+        assert!(Map::Standard.food_spawns(&grid).is_none());
+        let mut reached = vec![v2(1, 1)];
+        let mut i = 0;
+        while let Some(&p) = reached.get(i) {
+            for d in crate::env::Direction::iter() {
+                if let Some(n) = grid.neighbor(p, d) {
+                    if !grid.is_hazardous(n) && !reached.contains(&n) {
+                        reached.push(n);
+                    }
+                }
+            }
+            i += 1;
+        }
+        assert_eq!(reached.len(), free);
+        // The hazards do not change
+        let mut later = Grid::new(19, 21);
+        maze.update(&mut later, 1000, &settings, 7);
+        assert!(grid
+            .cells
+            .iter()
+            .zip(&later.cells)
+            .all(|(a, b)| a.hazards == b.hazards));
+
+        // Repeated cells are stacked
+        let mut grid = Grid::new(5, 5);
+        set_hazards(&mut grid, [v2(1, 1), v2(2, 2), v2(1, 1)]);
+        assert_eq!(grid[v2(1, 1)].hazards, 2);
+        assert_eq!(grid[v2(2, 2)].hazards, 1);
+        assert_eq!(
+            grid.damage(v2(1, 1)),
+            1 + 2 * settings.hazard_damage_per_turn
+        );
+        set_hazards(&mut grid, [v2(2, 2)]);
+        assert_eq!(grid[v2(1, 1)].hazards, 0);
+        assert_eq!(grid[v2(2, 2)].hazards, 1);
+    }
+}
@@ -1,4 +1,10 @@
-use std::path::Path;
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
 use tokio::{
     fs::{self, OpenOptions},
     io::AsyncWriteExt,
@@ -6,22 +12,253 @@ use tokio::{
 
 use crate::env::*;
 
-pub async fn save(game_req: GameRequest, log_dir: &Path) {
-    if !log_dir.exists() {
-        fs::create_dir(&log_dir)
-            .await
-            .expect("Logging directory could not be created!");
+/// Result of a recorded game from the perspective of our snake.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum GameResult {
+    Win,
+    Loss,
+    Draw,
+}
+
+impl GameResult {
+    /// Determines the result from the final request.
+    ///
+    /// We win if we survived, and lose if only others survived.
+    pub fn from_request(request: &GameRequest) -> Self {
+        let snakes = &request.board.snakes;
+        if snakes.iter().any(|s| s.id == request.you.id) {
+            GameResult::Win
+        } else if snakes.is_empty() {
+            GameResult::Draw
+        } else {
+            GameResult::Loss
+        }
+    }
+}
+
+/// Line of a savegame.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum Record {
+    Start {
+        request: GameRequest,
+    },
+    Move {
+        request: GameRequest,
+        r#move: Direction,
+        /// Response time in ms
+        time: u64,
+    },
+    End {
+        request: GameRequest,
+        result: GameResult,
+    },
+}
+
+/// Records the requests of games as JSON Lines files,
+/// one file per game and snake.
+#[derive(Debug, Clone)]
+pub struct Recorder {
+    dir: PathBuf,
+}
+
+impl Recorder {
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        Self { dir: dir.into() }
     }
 
-    let filename = format!("{}.{}.json", game_req.game.id, game_req.you.id);
-    let mut file = OpenOptions::new()
-        .write(true)
-        .create(true)
-        .append(true)
-        .open(log_dir.join(filename))
+    /// Path of the savegame of the game.
+    ///
+    /// The ids are sanitized, so that the savegames always stay in the directory.
+    pub fn path(&self, request: &GameRequest) -> PathBuf {
+        self.dir.join(format!(
+            "{}.{}.jsonl",
+            file_name(&request.game.id),
+            file_name(&request.you.id)
+        ))
+    }
+
+    /// Starts a new savegame, overwriting an existing one.
+    pub async fn start(&self, request: &GameRequest) -> io::Result<()> {
+        fs::create_dir_all(&self.dir).await?;
+        fs::write(self.path(request), b"").await?;
+        self.append(
+            request,
+            &Record::Start {
+                request: request.clone(),
+            },
+        )
+        .await
+    }
+
+    /// Records a request, together with our move and response time.
+    pub async fn record_move(
+        &self,
+        request: &GameRequest,
+        r#move: Direction,
+        time: Duration,
+    ) -> io::Result<()> {
+        self.append(
+            request,
+            &Record::Move {
+                request: request.clone(),
+                r#move,
+                time: time.as_millis() as u64,
+            },
+        )
         .await
-        .expect("Could not create/open save game!");
+    }
+
+    /// Finishes the savegame with the final request and the result of the game.
+    pub async fn end(&self, request: &GameRequest) -> io::Result<GameResult> {
+        let result = GameResult::from_request(request);
+        self.append(
+            request,
+            &Record::End {
+                request: request.clone(),
+                result,
+            },
+        )
+        .await?;
+        Ok(result)
+    }
+
+    async fn append(&self, request: &GameRequest, record: &Record) -> io::Result<()> {
+        fs::create_dir_all(&self.dir).await?;
+        let mut line = serde_json::to_vec(record)?;
+        line.push(b'\n');
+
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(self.path(request))
+            .await?;
+        file.write_all(&line).await?;
+        file.flush().await
+    }
+}
+
+/// Returns the id if it only contains `[A-Za-z0-9_-]`.
+///
+/// Otherwise, the other characters are replaced by `_` and a hash of the id is appended,
+/// so that different ids do not share a file.
+fn file_name(id: &str) -> String {
+    let valid = |c: char| c.is_ascii_alphanumeric() || c == '_' || c == '-';
+    if !id.is_empty() && id.chars().all(valid) {
+        return id.into();
+    }
+    let mut hasher = DefaultHasher::new();
+    id.hash(&mut hasher);
+    let name: String = id.chars().map(|c| if valid(c) { c } else { '_' }).collect();
+    format!("{name}-{:016x}", hasher.finish())
+}
+
+/// Loads all records of a savegame.
+pub async fn load(path: &Path) -> io::Result<Vec<Record>> {
+    let data = fs::read_to_string(path).await?;
+    data.lines()
+        .filter(|l| !l.trim().is_empty())
+        .map(|l| serde_json::from_str(l).map_err(io::Error::from))
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use crate::logging;
+
+    #[tokio::test]
+    async fn savegame() {
+        use super::*;
+        logging();
+
+        let request: GameRequest = serde_json::from_str(
+            r#"{"game":{"id":"g","ruleset":{"name":"standard"},"timeout":500},"turn":0,"board":{"width":11,"height":11,"food":[],"hazards":[],"snakes":[{"id":"a","name":"a","health":90,"body":[{"x":1,"y":1}]},{"id":"b","name":"b","health":90,"body":[{"x":5,"y":5}]}]},"you":{"id":"a","name":"a","health":90,"body":[{"x":1,"y":1}]}}"#,
+        )
+        .unwrap();
 
-    let data = serde_json::to_vec(&game_req).unwrap();
-    file.write_all(&data).await.unwrap();
+        let dir = std::env::temp_dir().join(format!("snork-savegame-{}", std::process::id()));
+        let recorder = Recorder::new(&dir);
+        recorder.start(&request).await.unwrap();
+        for _ in 0..2 {
+            recorder
+                .record_move(&request, Direction::Left, Duration::from_millis(42))
+                .await
+                .unwrap();
+        }
+        let mut last = request.clone();
+        last.board.snakes.remove(0);
+        assert_eq!(recorder.end(&last).await.unwrap(), GameResult::Loss);
+
+        let records = load(&recorder.path(&request)).await.unwrap();
+        assert_eq!(records.len(), 4);
+        assert!(matches!(records[0], Record::Start { .. }));
+        assert!(matches!(
+            records[2],
+            Record::Move {
+                r#move: Direction::Left,
+                time: 42,
+                ..
+            }
+        ));
+        assert!(matches!(
+            records[3],
+            Record::End {
+                result: GameResult::Loss,
+                ..
+            }
+        ));
+
+        // Restarting the game overwrites the savegame
+        recorder.start(&request).await.unwrap();
+        assert_eq!(load(&recorder.path(&request)).await.unwrap().len(), 1);
+
+        fs::remove_dir_all(&dir).await.unwrap();
+    }
+
+    #[test]
+    fn savegame_path() {
+        use super::*;
+        logging();
+
+        let request = |game: &str, you: &str| -> GameRequest {
+            serde_json::from_str(&format!(
+                r#"{{"game":{{"id":"{game}","ruleset":{{"name":"standard"}},"timeout":500}},"turn":0,"board":{{"width":11,"height":11,"food":[],"hazards":[],"snakes":[]}},"you":{{"id":"{you}","name":"a","health":90,"body":[{{"x":1,"y":1}}]}}}}"#
+            ))
+            .unwrap()
+        };
+
+        let dir = Path::new("/tmp/savegames");
+        let recorder = Recorder::new(dir);
+        assert_eq!(
+            recorder.path(&request("game-1", "gs_abc")),
+            dir.join("game-1.gs_abc.jsonl")
+        );
+
+        for (game, you) in [
+            ("../../etc/passwd", "a"),
+            ("g", "../../x"),
+            ("/abs", "a"),
+            ("..", ".."),
+            ("", ""),
+            ("a\\\\b", "c"),
+        ] {
+            let path = recorder.path(&request(game, you));
+            assert_eq!(path.parent(), Some(dir), "{path:?}");
+            let name = path.file_name().unwrap().to_str().unwrap();
+            assert!(
+                name.trim_end_matches(".jsonl")
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-' || c == '.'),
+                "{name}"
+            );
+            assert!(!name.starts_with('.'), "{name}");
+        }
+
+        // Sanitized ids do not collide with valid ones
+        assert_ne!(
+            recorder.path(&request("a/b", "c")),
+            recorder.path(&request("a_b", "c"))
+        );
+    }
 }
@@ -1,186 +1,261 @@
 use std::sync::Arc;
 
-use super::{Heuristic, DRAW, LOSS, WIN};
+use super::{limit_snakes, outcome_value, Bound, Entry, Heuristic, Line, SearchContext, LOSS, WIN};
 use crate::env::*;
-use crate::game::{Game, Outcome};
-
-use async_recursion::async_recursion;
+use crate::game::Game;
 
+/// Parallel version of the paranoid alpha-beta search.
+///
+/// The subtrees of our moves are searched in parallel, sharing the transposition table
+/// and the move ordering.
+/// Because they are searched with the full window, there is less pruning at the root.
 pub async fn async_alphabeta(
     game: &Game,
     depth: usize,
     heuristic: Arc<dyn Heuristic>,
+    ctx: Arc<SearchContext>,
 ) -> (Direction, f64) {
-    async_alphabeta_rec(game, [Direction::Up; 4], depth, 0, LOSS, WIN, heuristic).await
-}
+    let game = limit_snakes(game).into_owned();
+    let hash = game.hash();
+    if let Some(value) = lookup(&ctx, hash, depth, LOSS, WIN) {
+        return value;
+    }
 
-/// # WARNING
-/// This version is very slow, even slower than the synchronous alphabeta
-/// and much slower than multithreaded max n
-#[async_recursion]
-async fn async_alphabeta_rec(
-    game: &Game,
-    actions: [Direction; 4],
-    depth: usize,
-    ply: usize,
-    mut alpha: f64,
-    mut beta: f64,
-    heuristic: Arc<dyn Heuristic>,
-) -> (Direction, f64) {
-    if ply == game.snakes.len() {
+    let moves = ctx
+        .ordering
+        .order(&game, 0, &[], ctx.tt.get(hash).and_then(|e| e.best));
+    let mut tasks = Vec::with_capacity(moves.len());
+    for &d in moves.iter() {
         let mut game = game.clone();
-        game.step(&actions);
-        match game.outcome() {
-            Outcome::Winner(0) => return (Direction::Up, WIN),
-            Outcome::Winner(_) => return (Direction::Up, LOSS),
-            Outcome::Match => return (Direction::Up, DRAW),
-            Outcome::None => {}
-        }
+        let heuristic = heuristic.clone();
+        let ctx = ctx.clone();
+        tasks.push((
+            d,
+            tokio::task::spawn_blocking(move || {
+                let mut line = Line::new();
+                let (_, value) = alphabeta_rec(
+                    &mut game,
+                    &mut vec![d],
+                    depth,
+                    1,
+                    LOSS,
+                    WIN,
+                    &*heuristic,
+                    &ctx,
+                    &mut line,
+                );
+                (value, line)
+            }),
+        ));
+    }
 
-        if depth == 0 {
-            (Direction::Up, heuristic.eval(&game))
-        } else {
-            async_alphabeta_rec(
-                &game,
-                [Direction::Up; 4],
-                depth - 1,
-                0,
-                alpha,
-                beta,
-                heuristic,
-            )
-            .await
+    let mut value = (moves[0], LOSS);
+    let mut pv = Line::new();
+    for (d, task) in tasks {
+        if let Ok((newval, line)) = task.await {
+            if newval > value.1 {
+                value = (d, newval);
+                extend_line(&mut pv, d, &line);
+            }
         }
-    } else if ply == 0 {
-        let mut value = (Direction::Up, LOSS);
+    }
+    store(&ctx, hash, depth, LOSS, WIN, value);
+    if !ctx.is_cancelled() && !pv.is_empty() {
+        ctx.ordering.set_pv(&pv);
+    }
+    value
+}
 
-        let mut futures = [None, None, None, None];
-        for d in Direction::iter() {
-            let game = game.clone();
-            let heuristic = heuristic.clone();
-            let actions = [d, Direction::Up, Direction::Up, Direction::Up];
-            futures[d as u8 as usize] = Some(tokio::task::spawn(async move {
-                async_alphabeta_rec(&game, actions, depth, ply + 1, alpha, beta, heuristic).await
-            }));
-        }
+/// Returns the stored result if it is deep enough and applicable to the search window.
+fn lookup(
+    ctx: &SearchContext,
+    hash: u64,
+    depth: usize,
+    alpha: f64,
+    beta: f64,
+) -> Option<(Direction, f64)> {
+    let entry = ctx.tt.get(hash).filter(|e| e.depth >= depth)?;
+    let usable = match entry.bound {
+        Bound::Exact => true,
+        Bound::Lower => entry.value >= beta,
+        Bound::Upper => entry.value <= alpha,
+    };
+    usable.then(|| (entry.best.unwrap_or_default(), entry.value))
+}
 
-        for (i, future) in futures.into_iter().enumerate() {
-            if let Some(future) = future {
-                if let Ok(newval) = future.await {
-                    if newval.1 > value.1 {
-                        value = (Direction::from(i as u8), newval.1);
-                    }
-                    if newval.1 > alpha {
-                        alpha = newval.1;
-                    }
-                    if alpha >= beta {
-                        break;
-                    }
-                }
-            }
-        }
-        value
+/// Stores the result of a maximizing node that was searched with the window `alpha`, `beta`.
+///
+/// Results of cancelled searches are incomplete and not stored.
+fn store(
+    ctx: &SearchContext,
+    hash: u64,
+    depth: usize,
+    alpha: f64,
+    beta: f64,
+    (best, value): (Direction, f64),
+) {
+    if ctx.is_cancelled() {
+        return;
+    }
+    let bound = if value <= alpha {
+        Bound::Upper
+    } else if value >= beta {
+        Bound::Lower
     } else {
-        let mut value = (Direction::Up, WIN);
-        for d in Direction::iter() {
-            let mut actions = actions;
-            actions[ply] = d;
-            let newval = async_alphabeta_rec(
-                game,
-                actions,
-                depth,
-                ply + 1,
-                alpha,
-                beta,
-                heuristic.clone(),
-            )
-            .await;
-            if newval.1 < value.1 {
-                value = (d, newval.1);
-            }
-            if newval.1 < beta {
-                beta = newval.1;
-            }
-            if alpha >= beta {
-                break;
-            }
+        Bound::Exact
+    };
+    ctx.tt.insert(Entry {
+        hash,
+        depth,
+        value,
+        best: Some(best),
+        bound,
+    });
+}
+
+/// Replaces the `pv` with the move `d`, followed by the `line` of its subtree.
+fn extend_line(pv: &mut Line, d: Direction, line: &Line) {
+    *pv = Line::new();
+    pv.push(d);
+    for &d in line.iter() {
+        if !pv.push(d) {
+            break;
         }
-        value
     }
 }
 
-/// Alpha-Beta tree search.
+/// Paranoid alpha-beta search for up to four snakes.
 /// @see https://en.wikipedia.org/wiki/Alpha%E2%80%93beta_pruning
-/// Assuming the maximizing agent has id 0
-/// Assuming only two snakes are alive
-pub fn alphabeta(game: &Game, depth: usize, heuristic: &dyn Heuristic) -> (Direction, f64) {
-    alphabeta_rec(game, [Direction::Up; 4], depth, 0, LOSS, WIN, heuristic)
+///
+/// The player with id 0 is maximizing and all enemies form a coalition that minimizes
+/// its value, except for its squad members, which are maximizing too.
+/// The snakes choose their moves one after another, but the moves are applied
+/// simultaneously after all snakes have chosen.
+/// Dead snakes are skipped and distant ones are frozen.
+///
+/// Results of the maximizing player are cached in the transposition table of `ctx`,
+/// and its best move is searched first.
+/// The search stops as soon as `ctx` is cancelled, and its result is meaningless.
+/// Like `max_n`, a `depth` of 1 evaluates the heuristic after a single turn.
+pub fn alphabeta(
+    game: &Game,
+    depth: usize,
+    heuristic: &dyn Heuristic,
+    ctx: &SearchContext,
+) -> (Direction, f64) {
+    let mut game = limit_snakes(game).into_owned();
+    let mut pv = Line::new();
+    let result = alphabeta_rec(
+        &mut game,
+        &mut Vec::new(),
+        depth,
+        0,
+        LOSS,
+        WIN,
+        heuristic,
+        ctx,
+        &mut pv,
+    );
+    // The principal variation is followed first by the next iteration
+    if !ctx.is_cancelled() && !pv.is_empty() {
+        ctx.ordering.set_pv(&pv);
+    }
+    result
 }
 
+/// The `path` contains the moves from the root to this ply,
+/// and the principal variation of this node is stored in `pv`.
+#[allow(clippy::too_many_arguments)]
 fn alphabeta_rec(
-    game: &Game,
-    actions: [Direction; 4],
+    game: &mut Game,
+    path: &mut Vec<Direction>,
     depth: usize,
     ply: usize,
     mut alpha: f64,
     mut beta: f64,
     heuristic: &dyn Heuristic,
+    ctx: &SearchContext,
+    pv: &mut Line,
 ) -> (Direction, f64) {
     if ply == game.snakes.len() {
-        let mut game = game.clone();
-        game.step(&actions);
-        match game.outcome() {
-            Outcome::Winner(0) => return (Direction::Up, WIN),
-            Outcome::Winner(_) => return (Direction::Up, LOSS),
-            Outcome::Match => return (Direction::Up, DRAW),
-            Outcome::None => {}
+        if !ctx.visit() {
+            return (Direction::Up, LOSS);
         }
+        let undo = game.apply(&path[path.len() - ply..]);
+        let value = match outcome_value(game) {
+            Some(value) => value,
+            None if depth <= 1 => heuristic.eval(game),
+            None => alphabeta_rec(game, path, depth - 1, 0, alpha, beta, heuristic, ctx, pv).1,
+        };
+        game.undo(undo);
+        (Direction::Up, value)
+    } else if !game.snake_is_alive(ply as u8) {
+        // continue with next agent
+        path.push(Direction::Up);
+        let mut line = Line::new();
+        let value = alphabeta_rec(
+            game,
+            path,
+            depth,
+            ply + 1,
+            alpha,
+            beta,
+            heuristic,
+            ctx,
+            &mut line,
+        );
+        path.pop();
+        extend_line(pv, Direction::Up, &line);
+        value
+    } else {
+        // Squad members of the maximizing player are maximizing too
+        let maximize = ply == 0 || game.snakes[ply].allied(&game.snakes[0]);
 
-        if depth == 0 {
-            (Direction::Up, heuristic.eval(&game))
-        } else {
-            alphabeta_rec(
-                &game,
-                [Direction::Up; 4],
-                depth - 1,
-                0,
+        let hash = game.hash();
+        let alpha_start = alpha;
+        let mut first = None;
+        if ply == 0 {
+            if let Some(value) = lookup(ctx, hash, depth, alpha, beta) {
+                return value;
+            }
+            first = ctx.tt.get(hash).and_then(|e| e.best);
+        }
+
+        let moves = ctx.ordering.order(game, ply as u8, path, first);
+        let mut value = (moves[0], if maximize { LOSS } else { WIN });
+        for &d in moves.iter() {
+            path.push(d);
+            let mut line = Line::new();
+            let (_, newval) = alphabeta_rec(
+                game,
+                path,
+                depth,
+                ply + 1,
                 alpha,
                 beta,
                 heuristic,
-            )
-        }
-    } else if ply == 0 {
-        let mut value = (Direction::Up, LOSS);
-        for d in Direction::iter() {
-            let mut actions = actions;
-            actions[ply] = d;
-            let newval = alphabeta_rec(game, actions, depth, ply + 1, alpha, beta, heuristic);
-            if newval.1 > value.1 {
-                value = (d, newval.1);
+                ctx,
+                &mut line,
+            );
+            path.pop();
+
+            if (maximize && newval > value.1) || (!maximize && newval < value.1) {
+                value = (d, newval);
+                extend_line(pv, d, &line);
             }
-            if newval.1 > alpha {
-                alpha = newval.1;
+            if maximize && newval > alpha {
+                alpha = newval;
+            } else if !maximize && newval < beta {
+                beta = newval;
             }
             if alpha >= beta {
+                ctx.ordering.cutoff(game, ply as u8, path, d, depth);
                 break;
             }
         }
-        value
-    } else {
-        let mut value = (Direction::Up, WIN);
-        for d in Direction::iter() {
-            let mut actions = actions;
-            actions[ply] = d;
-            let newval = alphabeta_rec(game, actions, depth, ply + 1, alpha, beta, heuristic);
-            if newval.1 < value.1 {
-                value = (d, newval.1);
-            }
-            if newval.1 < beta {
-                beta = newval.1;
-            }
-            if alpha >= beta {
-                break;
-            }
+
+        if ply == 0 {
+            store(ctx, hash, depth, alpha_start, beta, value);
         }
         value
     }
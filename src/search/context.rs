@@ -0,0 +1,207 @@
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
+
+use super::{MoveOrdering, TranspositionTable, TT_CAPACITY};
+
+/// State that is shared by the (parallel) searches of an iterative deepening.
+///
+/// Besides the transposition table and the move ordering, this contains
+/// a cancellation flag, which is checked at every node, and counts the visited nodes.
+#[derive(Debug)]
+pub struct SearchContext {
+    pub tt: TranspositionTable,
+    pub ordering: MoveOrdering,
+    cancelled: AtomicBool,
+    nodes: AtomicUsize,
+}
+
+impl SearchContext {
+    /// Creates a context with a transposition table of the given `capacity`.
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            tt: TranspositionTable::new(capacity),
+            ordering: MoveOrdering::new(),
+            cancelled: AtomicBool::new(false),
+            nodes: AtomicUsize::new(0),
+        }
+    }
+
+    /// Replaces the move ordering.
+    pub fn with_ordering(mut self, ordering: MoveOrdering) -> Self {
+        self.ordering = ordering;
+        self
+    }
+
+    /// Stops all searches that use this context.
+    ///
+    /// The results of cancelled searches are meaningless and have to be discarded.
+    pub fn cancel(&self) {
+        self.cancelled.store(true, Ordering::Relaxed);
+    }
+
+    /// Clears the cancellation, so that the tables can be reused by new searches.
+    ///
+    /// All searches of the context have to be finished.
+    pub fn resume(&self) {
+        self.cancelled.store(false, Ordering::Relaxed);
+    }
+
+    /// Resets the tables, the cancellation, and the node count,
+    /// so that the context can be reused for an unrelated search.
+    ///
+    /// All searches of the context have to be finished.
+    pub fn clear(&self) {
+        self.tt.clear();
+        self.ordering.clear();
+        self.nodes.store(0, Ordering::Relaxed);
+        self.resume();
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::Relaxed)
+    }
+
+    /// Number of simulated turns.
+    pub fn nodes(&self) -> usize {
+        self.nodes.load(Ordering::Relaxed)
+    }
+
+    /// Counts a visited node and returns if the search should continue.
+    pub(super) fn visit(&self) -> bool {
+        self.nodes.fetch_add(1, Ordering::Relaxed);
+        !self.is_cancelled()
+    }
+}
+
+impl Default for SearchContext {
+    fn default() -> Self {
+        Self::new(TT_CAPACITY)
+    }
+}
+
+/// Statistics of an iterative deepening.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct SearchStats {
+    /// Depth of the last completed iteration
+    pub depth: usize,
+    /// Number of simulated turns, including the cancelled iteration
+    pub nodes: usize,
+    pub time: Duration,
+}
+
+/// Predicts whether the next iteration of an iterative deepening finishes in time.
+///
+/// The duration of the next iteration is extrapolated with the
+/// effective branching factor of the last two iterations.
+#[derive(Debug, Clone)]
+pub struct TimeManager {
+    start: Instant,
+    budget: Duration,
+    /// Nodes and time of the last two iterations
+    last: [Option<(usize, Duration)>; 2],
+}
+
+impl TimeManager {
+    pub fn new(budget: Duration) -> Self {
+        Self {
+            start: Instant::now(),
+            budget,
+            last: [None; 2],
+        }
+    }
+
+    pub fn elapsed(&self) -> Duration {
+        self.start.elapsed()
+    }
+
+    /// Records a completed iteration.
+    pub fn finished(&mut self, nodes: usize, time: Duration) {
+        self.last = [self.last[1], Some((nodes, time))];
+    }
+
+    /// Ratio of the nodes of the last two iterations.
+    pub fn branching_factor(&self) -> Option<f64> {
+        match self.last {
+            [Some((prev, _)), Some((last, _))] => Some(last as f64 / prev.max(1) as f64),
+            _ => None,
+        }
+    }
+
+    /// Expected duration of the next iteration.
+    pub fn predict(&self) -> Option<Duration> {
+        let (_, time) = self.last[1]?;
+        let factor = self.branching_factor()?.max(1.0);
+        Some(time.mul_f64(factor))
+    }
+
+    /// Returns if the next iteration is expected to finish in time.
+    pub fn next_fits(&self) -> bool {
+        let elapsed = self.elapsed();
+        match self.predict() {
+            Some(next) => elapsed + next <= self.budget,
+            None => elapsed < self.budget,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::logging;
+
+    #[test]
+    fn time_manager() {
+        use super::*;
+        logging();
+
+        let mut time = TimeManager::new(Duration::from_secs(60));
+        assert_eq!(time.predict(), None);
+        assert!(time.next_fits());
+
+        time.finished(100, Duration::from_millis(10));
+        assert_eq!(time.predict(), None);
+        time.finished(400, Duration::from_millis(40));
+        assert_eq!(time.branching_factor(), Some(4.0));
+        assert_eq!(time.predict(), Some(Duration::from_millis(160)));
+        assert!(time.next_fits());
+
+        // The next iteration would take longer than the budget
+        time.finished(400 * 1000, Duration::from_secs(40));
+        assert_eq!(time.predict(), Some(Duration::from_secs(40 * 1000)));
+        assert!(!time.next_fits());
+
+        let time = TimeManager::new(Duration::ZERO);
+        assert!(!time.next_fits());
+    }
+
+    #[test]
+    fn search_context() {
+        use super::*;
+        logging();
+
+        let ctx = SearchContext::new(1);
+        assert!(ctx.visit());
+        ctx.cancel();
+        assert!(!ctx.visit());
+        ctx.resume();
+        assert!(ctx.visit());
+        assert_eq!(ctx.nodes(), 3);
+
+        // Cleared contexts are like new ones
+        use crate::env::Direction;
+        use crate::search::{Bound, Entry};
+        ctx.tt.insert(Entry {
+            hash: 1,
+            depth: 1,
+            value: 0.0,
+            best: None,
+            bound: Bound::Exact,
+        });
+        ctx.ordering.set_pv(&[Direction::Left]);
+        ctx.cancel();
+        ctx.clear();
+        assert_eq!(ctx.nodes(), 0);
+        assert!(!ctx.is_cancelled());
+        assert_eq!(ctx.tt.get(1), None);
+        assert!(ctx.ordering.pv().is_empty());
+    }
+}
@@ -1,58 +1,77 @@
 use std::sync::Arc;
 
+use crate::env::Direction;
 use crate::game::Game;
-use crate::{env::Direction, game::Outcome};
+use crate::util::argmax;
 
 use async_recursion::async_recursion;
 
-use super::{Heuristic, DRAW, LOSS, WIN};
+use super::{limit_snakes, outcome_value, Bound, Entry, Heuristic, SearchContext, DRAW, LOSS, WIN};
 
 /// This algorithm is more or less a hacky variation of minmax with multiple agents.
-/// The player with id 0 is the maximizing player, the others are minimizing.
+/// The player with id 0 is the maximizing player, the others are minimizing
+/// (except for its squad members).
 ///
 /// The return value contains the heuristic for each of the four moves of the maximizing player.
 ///
 /// If the maximizing player dies traversal ends and min is returned.
-/// Dead enemies are skipped.
-pub async fn async_max_n(game: &Game, depth: usize, heuristic: Arc<dyn Heuristic>) -> [f64; 4] {
-    assert!(game.snakes.len() <= 4);
-    async_max_n_rec(game, depth, 0, [Direction::Up; 4], heuristic).await
+/// Dead enemies are skipped and distant ones are frozen.
+/// Subtree results are cached in the transposition table of the context `ctx`.
+/// The search stops as soon as `ctx` is cancelled, and its result is meaningless.
+pub async fn async_max_n(
+    game: &Game,
+    depth: usize,
+    heuristic: Arc<dyn Heuristic>,
+    ctx: Arc<SearchContext>,
+) -> [f64; 4] {
+    let mut game = limit_snakes(game).into_owned();
+    async_max_n_rec(&mut game, depth, 0, [Direction::Up; 4], heuristic, ctx).await
 }
 
 #[async_recursion]
 async fn async_max_n_rec(
-    game: &Game,
+    game: &mut Game,
     depth: usize,
     ply: usize,
     actions: [Direction; 4],
     heuristic: Arc<dyn Heuristic>,
+    ctx: Arc<SearchContext>,
 ) -> [f64; 4] {
     if ply == game.snakes.len() {
-        // simulate
-        let mut game = game.clone();
-        game.step(&actions[..]);
-
-        match game.outcome() {
-            Outcome::Winner(0) => return [WIN + heuristic.eval(&game), DRAW, DRAW, DRAW],
-            Outcome::Winner(_) => return [LOSS; 4],
-            Outcome::Match => return [DRAW; 4],
-            Outcome::None => {}
+        if !ctx.visit() {
+            return [LOSS; 4];
         }
 
-        if depth <= 1 {
+        // simulate
+        let undo = game.apply(&actions[..]);
+
+        let result = match outcome_value(game) {
+            Some(value) => [value, DRAW, DRAW, DRAW],
             // eval
-            [heuristic.eval(&game), DRAW, DRAW, DRAW]
-        } else {
-            let mut result =
-                async_max_n_rec(&game, depth - 1, 0, [Direction::Up; 4], heuristic).await;
-            // max
-            for i in 1..4 {
-                if result[i] > result[0] {
-                    result[0] = result[i];
+            None if depth <= 1 => [heuristic.eval(game), DRAW, DRAW, DRAW],
+            None => match ctx.tt.get(game.hash()) {
+                // Bounds of the alpha-beta search are no exact values
+                Some(entry) if entry.depth >= depth - 1 && entry.bound == Bound::Exact => {
+                    [entry.value, DRAW, DRAW, DRAW]
                 }
-            }
-            result
-        }
+                _ => {
+                    let hash = game.hash();
+                    let result = async_max_n_rec(
+                        game,
+                        depth - 1,
+                        0,
+                        [Direction::Up; 4],
+                        heuristic,
+                        ctx.clone(),
+                    )
+                    .await;
+                    [store_max(&ctx, hash, depth - 1, result), DRAW, DRAW, DRAW]
+                }
+            },
+        };
+
+        game.undo(undo);
+        result
     } else if ply == 0 {
         // collect all outcomes instead of max
         let mut result = [LOSS; 4];
@@ -65,12 +84,13 @@ async fn async_max_n_rec(
             }
 
             let actions = [d, Direction::Up, Direction::Up, Direction::Up];
-            let game = game.clone();
+            let mut game = game.clone();
             let heuristic = heuristic.clone();
+            let ctx = ctx.clone();
 
             // Create tasks for subtrees.
             futures[d as u8 as usize] = Some(tokio::task::spawn(async move {
-                async_max_n_rec(&game, depth, ply + 1, actions, heuristic).await
+                async_max_n_rec(&mut game, depth, ply + 1, actions, heuristic, ctx).await
             }));
         }
         for (i, future) in futures.into_iter().enumerate() {
@@ -83,7 +103,9 @@ async fn async_max_n_rec(
 
         result
     } else {
-        let mut min = 2.0 * WIN;
+        // Squad members of the maximizing player are maximizing too
+        let ally = game.snakes[ply].allied(&game.snakes[0]);
+        let mut best = if ally { -2.0 * WIN } else { 2.0 * WIN };
         let mut moved = false;
         for d in Direction::iter() {
             if !game.move_is_valid(ply as u8, d) {
@@ -92,69 +114,105 @@ async fn async_max_n_rec(
 
             let mut actions = actions;
             actions[ply] = d;
-            let val = async_max_n_rec(game, depth, ply + 1, actions, heuristic.clone()).await[0];
-            if val < min {
-                min = val;
+            let val = async_max_n_rec(
+                game,
+                depth,
+                ply + 1,
+                actions,
+                heuristic.clone(),
+                ctx.clone(),
+            )
+            .await[0];
+            if (ally && val > best) || (!ally && val < best) {
+                best = val;
                 moved = true;
 
                 // skip if already lowest possible outcome
-                if val <= LOSS {
+                if !ally && val <= LOSS {
                     break;
                 }
             }
         }
         if !moved {
             // continue with next agent
-            min = async_max_n_rec(game, depth, ply + 1, actions, heuristic).await[0];
+            best = async_max_n_rec(game, depth, ply + 1, actions, heuristic, ctx).await[0];
         }
-        [min, DRAW, DRAW, DRAW]
+        [best, DRAW, DRAW, DRAW]
     }
 }
 
 /// This algorithm is more or less a hacky variation of minmax with multiple agents.
-/// The player with id 0 is the maximizing player, the others are minimizing.
+/// The player with id 0 is the maximizing player, the others are minimizing
+/// (except for its squad members).
 ///
 /// The return value contains the heuristic for each of the four moves of the maximizing player.
 ///
-/// If the maximizing player dies traversal ends and min is returned.
-/// Dead enemies are skipped.
-pub fn max_n(game: &Game, depth: usize, heuristic: &dyn Heuristic) -> [f64; 4] {
-    assert!(game.snakes.len() <= 4);
-    max_n_rec(game, depth, 0, [Direction::Up; 4], heuristic)
+/// If the maximizing player dies traversal ends and best is returned.
+/// Dead enemies are skipped and distant ones are frozen.
+/// Subtree results are cached in the transposition table of the context `ctx`.
+/// The search stops as soon as `ctx` is cancelled, and its result is meaningless.
+pub fn max_n(
+    game: &Game,
+    depth: usize,
+    heuristic: &dyn Heuristic,
+    ctx: &SearchContext,
+) -> [f64; 4] {
+    let mut game = limit_snakes(game).into_owned();
+    max_n_rec(&mut game, depth, 0, [Direction::Up; 4], heuristic, ctx)
+}
+
+/// Stores the best move and value of the maximizing player in the transposition table.
+///
+/// Results of cancelled searches are incomplete and not stored.
+fn store_max(ctx: &SearchContext, hash: u64, depth: usize, result: [f64; 4]) -> f64 {
+    let best = argmax(result.iter()).unwrap_or_default();
+    if !ctx.is_cancelled() {
+        ctx.tt.insert(Entry {
+            hash,
+            depth,
+            value: result[best],
+            best: Some(Direction::from(best as u8)),
+            bound: Bound::Exact,
+        });
+    }
+    result[best]
 }
 
 fn max_n_rec(
-    game: &Game,
+    game: &mut Game,
     depth: usize,
     ply: usize,
     actions: [Direction; 4],
     heuristic: &dyn Heuristic,
+    ctx: &SearchContext,
 ) -> [f64; 4] {
     if ply == game.snakes.len() {
-        // simulate
-        let mut game = game.clone();
-        game.step(&actions[..]);
-
-        match game.outcome() {
-            Outcome::Winner(0) => return [WIN + heuristic.eval(&game), DRAW, DRAW, DRAW],
-            Outcome::Winner(_) => return [LOSS; 4],
-            Outcome::Match => return [DRAW; 4],
-            Outcome::None => {}
+        if !ctx.visit() {
+            return [LOSS; 4];
         }
 
-        if depth <= 1 {
+        // simulate
+        let undo = game.apply(&actions[..]);
+
+        let result = match outcome_value(game) {
+            Some(value) => [value, DRAW, DRAW, DRAW],
             // eval
-            [heuristic.eval(&game), DRAW, DRAW, DRAW]
-        } else {
-            let mut result = max_n_rec(&game, depth - 1, 0, [Direction::Up; 4], heuristic);
-            // max
-            for i in 1..4 {
-                if result[i] > result[0] {
-                    result[0] = result[i];
+            None if depth <= 1 => [heuristic.eval(game), DRAW, DRAW, DRAW],
+            None => match ctx.tt.get(game.hash()) {
+                // Bounds of the alpha-beta search are no exact values
+                Some(entry) if entry.depth >= depth - 1 && entry.bound == Bound::Exact => {
+                    [entry.value, DRAW, DRAW, DRAW]
                 }
-            }
-            result
-        }
+                _ => {
+                    let hash = game.hash();
+                    let result = max_n_rec(game, depth - 1, 0, [Direction::Up; 4], heuristic, ctx);
+                    [store_max(ctx, hash, depth - 1, result), DRAW, DRAW, DRAW]
+                }
+            },
+        };
+
+        game.undo(undo);
+        result
     } else if ply == 0 {
         // collect all outcomes instead of max
         let mut result = [LOSS; 4];
@@ -164,11 +222,13 @@ fn max_n_rec(
             }
             let mut actions = actions;
             actions[ply] = d;
-            result[d as u8 as usize] = max_n_rec(game, depth, ply + 1, actions, heuristic)[0];
+            result[d as u8 as usize] = max_n_rec(game, depth, ply + 1, actions, heuristic, ctx)[0];
         }
         result
     } else {
-        let mut min = 2.0 * WIN;
+        // Squad members of the maximizing player are maximizing too
+        let ally = game.snakes[ply].allied(&game.snakes[0]);
+        let mut best = if ally { -2.0 * WIN } else { 2.0 * WIN };
         let mut moved = false;
         for d in Direction::iter() {
             if !game.move_is_valid(ply as u8, d) {
@@ -177,22 +237,22 @@ fn max_n_rec(
 
             let mut actions = actions;
             actions[ply] = d;
-            let val = max_n_rec(game, depth, ply + 1, actions, heuristic)[0];
-            if val < min {
-                min = val;
+            let val = max_n_rec(game, depth, ply + 1, actions, heuristic, ctx)[0];
+            if (ally && val > best) || (!ally && val < best) {
+                best = val;
                 moved = true;
 
                 // skip if already lowest possible outcome
-                if val <= LOSS {
+                if !ally && val <= LOSS {
                     break;
                 }
             }
         }
         if !moved {
             // continue with next agent
-            min = max_n_rec(game, depth, ply + 1, actions, heuristic)[0];
+            best = max_n_rec(game, depth, ply + 1, actions, heuristic, ctx)[0];
         }
-        [min, DRAW, DRAW, DRAW]
+        [best, DRAW, DRAW, DRAW]
     }
 }
 
@@ -203,7 +263,7 @@ mod test {
     use crate::floodfill::FloodFill;
     use crate::game::Game;
     use crate::logging;
-    use crate::search::{alphabeta, Heuristic};
+    use crate::search::{alphabeta, Heuristic, MoveOrdering};
 
     #[derive(Debug, Clone, Default)]
     struct TestH;
@@ -211,7 +271,7 @@ mod test {
         fn eval(&self, game: &Game) -> f64 {
             let mut flood_fill = FloodFill::new(game.grid.width, game.grid.height);
             if game.snake_is_alive(0) {
-                flood_fill.flood_snakes(&game.grid, &game.snakes);
+                flood_fill.flood_snakes(game);
                 flood_fill.count_space(0) as f64
             } else {
                 0.0
@@ -224,7 +284,7 @@ mod test {
     fn max_n() {
         use super::*;
         use crate::env::Vec2D;
-        use crate::game::Snake;
+        use crate::game::{Mode, Snake};
         use std::time::Instant;
         logging();
 
@@ -253,11 +313,11 @@ mod test {
             ),
         ];
 
-        let game = Game::new(0, 11, 11, snakes, &[], &[]);
+        let game = Game::new(0, Mode::Standard, 11, 11, snakes, &[], &[]);
         info!("{:?}", game.grid);
         let start = Instant::now();
 
-        let moves = max_n(&game, 3, &TestH);
+        let moves = max_n(&game, 3, &TestH, &SearchContext::default());
         let end = Instant::now();
         info!("{:?}", moves);
         info!("time {}ms", (end - start).as_millis());
@@ -268,7 +328,7 @@ mod test {
     async fn async_max_n() {
         use super::*;
         use crate::env::Vec2D;
-        use crate::game::Snake;
+        use crate::game::{Mode, Snake};
         use std::time::Instant;
         logging();
 
@@ -297,10 +357,16 @@ mod test {
             ),
         ];
 
-        let game = Game::new(0, 11, 11, snakes, &[], &[]);
+        let game = Game::new(0, Mode::Standard, 11, 11, snakes, &[], &[]);
         info!("{:?}", game.grid);
         let start = Instant::now();
-        let moves = async_max_n(&game, 3, Arc::new(TestH)).await;
+        let moves = async_max_n(
+            &game,
+            3,
+            Arc::new(TestH),
+            Arc::new(SearchContext::default()),
+        )
+        .await;
         let end = Instant::now();
         info!("{:?}", moves);
         info!("async time {}ms", (end - start).as_millis());
@@ -311,7 +377,7 @@ mod test {
     fn duel() {
         use super::*;
         use crate::env::Vec2D;
-        use crate::game::Snake;
+        use crate::game::{Mode, Snake};
         use std::time::Instant;
         logging();
 
@@ -332,19 +398,365 @@ mod test {
             ),
         ];
 
-        let game = Game::new(0, 11, 11, snakes, &[], &[]);
+        let game = Game::new(0, Mode::Standard, 11, 11, snakes, &[], &[]);
         info!("{:?}", game.grid);
 
         let start = Instant::now();
-        let moves = max_n(&game, 6, &TestH);
+        let moves = max_n(&game, 6, &TestH, &SearchContext::default());
         let end = Instant::now();
         info!("max_n {:?}", moves);
         info!("max_n time {}ms", (end - start).as_millis());
 
         let start = Instant::now();
-        let moves = alphabeta(&game, 6, &TestH);
+        let moves = alphabeta(&game, 6, &TestH, &SearchContext::default());
         let end = Instant::now();
         info!("alpha_beta {:?}", moves);
         info!("alpha_beta time {}ms", (end - start).as_millis());
     }
+
+    #[test]
+    fn max_n_many_snakes() {
+        use super::*;
+        use crate::env::Direction;
+        logging();
+
+        let game = Game::parse(
+            r#"
+            5 . . . . . . . . . 4
+            ^ . . . . . . . . . ^
+            ^ . . . . . . . . . ^
+            . . . . . . . . . . .
+            . . . . . . . . . . .
+            . . . . 1 . 0 . . . .
+            . . . . ^ . ^ . . . .
+            . . . 3 ^ . ^ . 2 . .
+            . . . ^ . . . . ^ . .
+            . . . ^ . . . . ^ . .
+            . . . . . . . . . . ."#,
+        )
+        .unwrap();
+
+        let moves = max_n(&game, 2, &TestH, &SearchContext::default());
+        info!("{:?}", moves);
+        assert!(moves[Direction::Down as usize] <= LOSS);
+        assert!(moves[Direction::Up as usize] > LOSS);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn cancellation() {
+        use super::*;
+        use crate::search::async_alphabeta;
+        logging();
+
+        let game = Game::parse(
+            r#"
+            . . . . . . . . . . .
+            . . . . . . . . . . .
+            . . . 0 . . . . . . .
+            . . . ^ . . . . . . .
+            . . . ^ . . . . . . .
+            . . . . . . . . . . .
+            . . . . . . . 1 . . .
+            . . . . . . . ^ . . .
+            . . . . . . . ^ . . .
+            . . . . . . . . . . .
+            . . . . . . . . . . ."#,
+        )
+        .unwrap();
+
+        let ctx = SearchContext::default();
+        max_n(&game, 2, &TestH, &ctx);
+        let nodes = ctx.nodes();
+        info!("nodes {nodes}");
+        assert!(nodes > 16);
+
+        // Cancelled searches stop at the first turn and do not pollute the table
+        for depth in [2, 8] {
+            let ctx = Arc::new(SearchContext::default());
+            ctx.cancel();
+            max_n(&game, depth, &TestH, &ctx);
+            async_max_n(&game, depth, Arc::new(TestH), ctx.clone()).await;
+            alphabeta(&game, depth, &TestH, &ctx);
+            async_alphabeta(&game, depth, Arc::new(TestH), ctx.clone()).await;
+            info!("cancelled nodes {}", ctx.nodes());
+            assert!(ctx.nodes() <= 4 * 16);
+            assert_eq!(ctx.tt.get(game.hash()), None);
+        }
+    }
+
+    #[test]
+    fn transpositions() {
+        use super::*;
+        logging();
+
+        let game = Game::parse(
+            r#"
+            . . . . . . . . . . .
+            . . . . . . . . . . .
+            . . . . . . . . . . .
+            . . . 0 . . . . . . .
+            . . . ^ . . . . . . .
+            . . . ^ . . o . . . .
+            . . . . . . . . . . .
+            . . . . . . . 1 . . .
+            . . . . . . . ^ . . .
+            . . . . . . . ^ . . .
+            . . . . . . . . . . ."#,
+        )
+        .unwrap();
+
+        // A single slot is overwritten constantly, so there are (almost) no hits
+        let uncached = max_n(&game, 3, &TestH, &SearchContext::new(1));
+        let ctx = SearchContext::default();
+        let cached = max_n(&game, 3, &TestH, &ctx);
+        info!("{uncached:?} {cached:?}");
+        assert_eq!(uncached, cached);
+        // Searching again is answered by the table
+        assert_eq!(max_n(&game, 3, &TestH, &ctx), cached);
+
+        let uncached = alphabeta(&game, 3, &TestH, &SearchContext::new(1));
+        let ctx = SearchContext::default();
+        let cached = alphabeta(&game, 3, &TestH, &ctx);
+        info!("{uncached:?} {cached:?}");
+        assert_eq!(uncached.1, cached.1);
+        // Iterative deepening with a shared table finds the same result
+        let ctx = SearchContext::default();
+        let mut deepened = alphabeta(&game, 1, &TestH, &ctx);
+        for depth in 2..=3 {
+            deepened = alphabeta(&game, depth, &TestH, &ctx);
+        }
+        assert_eq!(deepened.1, cached.1);
+        let entry = ctx.tt.get(game.hash()).unwrap();
+        assert_eq!(entry.depth, 3);
+        assert_eq!(entry.best, Some(deepened.0));
+    }
+
+    #[test]
+    fn transpositions_bounds() {
+        use super::*;
+        use crate::search::{Bound, Entry};
+        logging();
+
+        let game = Game::parse(
+            r#"
+            . . . . . . . . . . .
+            . . . . . . . . . . .
+            . . . . . . . . . . .
+            . . . 0 . . . . . . .
+            . . . ^ . . . . . . .
+            . . . ^ . . o . . . .
+            . . . . . . . . . . .
+            . . . . . . . 1 . . .
+            . . . . . . . ^ . . .
+            . . . . . . . ^ . . .
+            . . . . . . . . . . ."#,
+        )
+        .unwrap();
+        let expected = max_n(&game, 2, &TestH, &SearchContext::default());
+
+        // Alpha-beta bounds of all states after the first turn
+        let ctx = SearchContext::default();
+        for bound in [Bound::Lower, Bound::Upper] {
+            for a in Direction::iter() {
+                for b in Direction::iter() {
+                    let mut game = game.clone();
+                    let undo = game.apply(&[a, b]);
+                    ctx.tt.insert(Entry {
+                        hash: game.hash(),
+                        depth: 8,
+                        value: WIN,
+                        best: Some(Direction::Up),
+                        bound,
+                    });
+                    game.undo(undo);
+                }
+            }
+            // are ignored by max_n
+            assert_eq!(max_n(&game, 2, &TestH, &ctx), expected);
+        }
+    }
+
+    #[test]
+    fn alphabeta_paranoid() {
+        use super::*;
+        use crate::env::Direction;
+        use crate::game::Mode;
+        logging();
+
+        let game = Game::parse(
+            r#"
+            . . . . . . . . . . .
+            . . . . . . . . . . .
+            . . . . . . . . . . .
+            . . . . . . . . . . .
+            . . . . . . . . 3 . .
+            . . . . 1 . 0 . ^ . .
+            . . . . ^ . ^ . ^ . .
+            . . . . ^ . ^ . . . .
+            . . . . . . . . . . .
+            . . 2 . . . . . . . .
+            . . ^ . . . . . . . ."#,
+        )
+        .unwrap();
+
+        // The snake 2 bites itself
+        let mut dead = game.clone();
+        dead.step(&[Direction::Up, Direction::Up, Direction::Down, Direction::Up]);
+        assert!(!dead.snake_is_alive(2));
+
+        // The snakes 1 and 2 are our allies
+        let mut squad = game.clone();
+        squad.set_mode(Mode::Squad);
+        for (i, snake) in squad.snakes.iter_mut().enumerate() {
+            snake.squad = Some((i % 2) as u8);
+        }
+
+        // Paranoid alpha-beta computes the same values as max_n, which is paranoid too
+        for game in [game, dead, squad] {
+            for depth in 1..=2 {
+                let expected = max_n(&game, depth, &TestH, &SearchContext::default());
+                let expected = expected.iter().copied().fold(f64::NEG_INFINITY, f64::max);
+                let (dir, value) = alphabeta(&game, depth, &TestH, &SearchContext::default());
+                info!("depth {depth}: {dir:?} {value} {expected}");
+                assert_eq!(value, expected);
+                assert!(game.move_is_valid(0, dir));
+            }
+        }
+    }
+
+    #[test]
+    fn squad_ally_wins() {
+        use super::*;
+        use crate::game::Mode;
+        logging();
+
+        let mut game = Game::parse(
+            r#"
+            . . . . . . . . . . .
+            . . . . . . . . . . .
+            . . . . . . . . . . .
+            . . . . . . . . . . .
+            . . . . . . . . . . .
+            . . . . 1 . 0 . . . .
+            . . . . ^ . ^ . . . .
+            . . . . ^ . ^ . . . .
+            . . . . . . . . . . .
+            . . 2 . . . . . . . .
+            . . ^ . . . . . . . ."#,
+        )
+        .unwrap();
+        // We and the enemy starve, only our ally survives
+        game.snakes[0].health = 1;
+        game.snakes[2].health = 1;
+
+        // Without squads, the survivor is an enemy
+        for depth in 1..=2 {
+            let moves = max_n(&game, depth, &TestH, &SearchContext::default());
+            info!("{moves:?}");
+            assert!(moves.iter().all(|&v| v == LOSS));
+            let (_, value) = alphabeta(&game, depth, &TestH, &SearchContext::default());
+            assert_eq!(value, LOSS);
+        }
+
+        // A win of our squad member is our win
+        game.set_mode(Mode::Squad);
+        let mut settings = game.settings;
+        settings.squad.shared_elimination = false;
+        settings.squad.shared_health = false;
+        game.set_settings(settings);
+        game.snakes[0].squad = Some(0);
+        game.snakes[1].squad = Some(0);
+        game.snakes[2].squad = Some(1);
+        for depth in 1..=2 {
+            let moves = max_n(&game, depth, &TestH, &SearchContext::default());
+            info!("{moves:?}");
+            assert!(Direction::iter()
+                .filter(|&d| game.move_is_valid(0, d))
+                .all(|d| moves[d as usize] == WIN));
+            let (_, value) = alphabeta(&game, depth, &TestH, &SearchContext::default());
+            assert_eq!(value, WIN);
+        }
+    }
+
+    #[test]
+    fn alphabeta_ordering() {
+        use super::*;
+        logging();
+
+        let games = [
+            r#"
+            . . . . . . . . . . .
+            . . . . . . . . . . .
+            . . . . . . . . . . .
+            . . . 0 . . . . . . .
+            . . . ^ . . . . . . .
+            . . . ^ . . o . . . .
+            . . . . . . . . . . .
+            . . . . . . . 1 . . .
+            . . . . . . . ^ . . .
+            . . . . . . . ^ . . .
+            . . . . . . . . . . ."#,
+            r#"
+            . . . . . . . . . . .
+            . . . . . . . . . . .
+            . . . . . . . . . . .
+            . . . . . . . . . . .
+            . . . . . . . . 3 . .
+            . . . . 1 . 0 . ^ . .
+            . . . . ^ . ^ . ^ . .
+            . . . . ^ . ^ . . . .
+            . . . . . . . . . . .
+            . . 2 . . . . . . . .
+            . . ^ . . . . . . . ."#,
+        ];
+
+        for game in games {
+            let game = Game::parse(game).unwrap();
+            let fixed = SearchContext::default().with_ordering(MoveOrdering::fixed());
+            let ordered = SearchContext::default();
+
+            // Iterative deepening, like the agents
+            for depth in 1..=3 {
+                let expected = alphabeta(&game, depth, &TestH, &fixed);
+                let (dir, value) = alphabeta(&game, depth, &TestH, &ordered);
+                info!("depth {depth}: {dir:?} {value} {expected:?}");
+                assert_eq!(value, expected.1);
+                assert_eq!(ordered.ordering.pv()[0], dir);
+            }
+            info!("nodes: fixed {} ordered {}", fixed.nodes(), ordered.nodes());
+            assert!(ordered.nodes() < fixed.nodes());
+        }
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn async_alphabeta_paranoid() {
+        use super::*;
+        use crate::search::async_alphabeta;
+        logging();
+
+        let game = Game::parse(
+            r#"
+            . . . . . . .
+            . . . . . . .
+            . . 1 . 2 . .
+            . . ^ . ^ . .
+            . 0 ^ . ^ . .
+            . ^ . . . . .
+            . ^ . . . . ."#,
+        )
+        .unwrap();
+
+        for depth in 1..=3 {
+            let (_, expected) = alphabeta(&game, depth, &TestH, &SearchContext::default());
+            let (dir, value) = async_alphabeta(
+                &game,
+                depth,
+                Arc::new(TestH),
+                Arc::new(SearchContext::default()),
+            )
+            .await;
+            info!("depth {depth}: {dir:?} {value} {expected}");
+            assert_eq!(value, expected);
+        }
+    }
 }
@@ -2,16 +2,68 @@ mod minimax;
 pub use minimax::*;
 mod alphabeta;
 pub use alphabeta::*;
+mod transposition;
+pub use transposition::*;
+mod context;
+pub use context::*;
+mod ordering;
+pub use ordering::*;
 
+use std::borrow::Cow;
 use std::fmt::Debug;
 
-use crate::game::Game;
+use crate::game::{Game, Outcome};
 
 pub const WIN: f64 = 10000.0;
 pub const DRAW: f64 = 0.0;
 pub const LOSS: f64 = -10000.0;
 
+/// Maximum number of snakes that are simulated by the tree searches.
+pub const MAX_SNAKES: usize = 4;
+
+/// Freezes the most distant snakes if there are more than `MAX_SNAKES`.
+fn limit_snakes(game: &Game) -> Cow<'_, Game> {
+    if game.snakes.len() > MAX_SNAKES {
+        let mut game = game.clone();
+        game.freeze_distant(MAX_SNAKES);
+        Cow::Owned(game)
+    } else {
+        Cow::Borrowed(game)
+    }
+}
+
+/// Value of a finished game for the player 0, or `None` if the game continues.
+///
+/// Wins of squad members count as our own, and our death is a loss
+/// even if the others are still playing.
+fn outcome_value(game: &Game) -> Option<f64> {
+    match game.outcome() {
+        Outcome::Winner(w) if w == 0 || game.snakes[w as usize].allied(&game.snakes[0]) => {
+            Some(WIN)
+        }
+        Outcome::Winner(_) => Some(LOSS),
+        Outcome::Match => Some(DRAW),
+        Outcome::None if !game.snake_is_alive(0) => Some(LOSS),
+        Outcome::None => None,
+    }
+}
+
+/// Tree search algorithm that is used by the agents.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, serde::Serialize, serde::Deserialize)]
+pub enum Search {
+    /// [`max_n`] that searches all moves of the enemies.
+    #[default]
+    MaxN,
+    /// Paranoid [`alphabeta`] that prunes moves, which are not better than the known ones.
+    AlphaBeta,
+}
+
 /// A heuristic that evaluates the game state at the leafs of a tree search.
 pub trait Heuristic: Debug + Send + Sync + 'static {
     fn eval(&self, game: &Game) -> f64;
+
+    /// Search algorithm that is used with this heuristic.
+    fn search(&self) -> Search {
+        Search::default()
+    }
 }
@@ -0,0 +1,263 @@
+use std::cmp::Reverse;
+use std::sync::atomic::{AtomicU32, AtomicU8, AtomicUsize, Ordering};
+
+use crate::env::Direction;
+use crate::game::Game;
+use crate::util::FixedVec;
+
+use super::MAX_SNAKES;
+
+/// Maximum number of plies (moves of single snakes) that are tracked by the move ordering.
+pub const MAX_PLY: usize = 64;
+
+/// Moves of the snakes, one after another, starting at the root of the search.
+pub type Line = FixedVec<Direction, MAX_PLY>;
+
+/// Head positions are wrapped into a 32x32 grid for the history table.
+const HISTORY_CELLS: usize = 32 * 32;
+
+/// Orders the moves of the tree searches, so that alpha-beta can prune earlier.
+///
+/// The moves are searched in the following order:
+/// 1. The best move from the transposition table
+/// 2. The move of the principal variation of the previous iteration,
+///    if all moves up to this ply followed it
+/// 3. The killer moves, which caused cutoffs at the same ply
+/// 4. The remaining moves by their history score
+///
+/// All tables are updated atomically, so that the parallel subtree searches can share them.
+/// Concurrent updates may be lost, which only affects the quality of the ordering.
+pub struct MoveOrdering {
+    enabled: bool,
+    /// Principal variation, encoded as `Direction + 1`
+    pv: [AtomicU8; MAX_PLY],
+    pv_len: AtomicUsize,
+    /// Two killer moves per ply, encoded as `Direction + 1`
+    killers: [[AtomicU8; 2]; MAX_PLY],
+    /// Cutoff scores by snake, head position and move
+    history: Vec<AtomicU32>,
+}
+
+impl MoveOrdering {
+    /// Creates an ordering with principal variation, killer moves and history heuristic.
+    pub fn new() -> Self {
+        Self {
+            enabled: true,
+            pv: [(); MAX_PLY].map(|_| AtomicU8::new(0)),
+            pv_len: AtomicUsize::new(0),
+            killers: [(); MAX_PLY].map(|_| [AtomicU8::new(0), AtomicU8::new(0)]),
+            history: (0..MAX_SNAKES * HISTORY_CELLS * 4)
+                .map(|_| AtomicU32::new(0))
+                .collect(),
+        }
+    }
+
+    /// Creates an ordering that only prefers the transposition table move,
+    /// and otherwise keeps the fixed order of `Direction::iter`.
+    pub fn fixed() -> Self {
+        Self {
+            enabled: false,
+            ..Self::new()
+        }
+    }
+
+    /// Forgets the principal variation, the killer moves, and the history scores.
+    pub fn clear(&self) {
+        self.pv_len.store(0, Ordering::Relaxed);
+        for v in self.pv.iter().chain(self.killers.iter().flatten()) {
+            v.store(0, Ordering::Relaxed);
+        }
+        for v in &self.history {
+            v.store(0, Ordering::Relaxed);
+        }
+    }
+
+    fn history_index(game: &Game, snake: u8, d: Direction) -> Option<usize> {
+        let snake = snake as usize;
+        if snake >= MAX_SNAKES {
+            return None;
+        }
+        let head = game.snakes[snake].head();
+        let cell = (head.x as usize & 31) | (head.y as usize & 31) << 5;
+        Some((snake * HISTORY_CELLS + cell) * 4 + d as usize)
+    }
+
+    fn decode(v: u8) -> Option<Direction> {
+        (v > 0).then(|| Direction::from(v - 1))
+    }
+
+    /// Returns the principal variation of the last completed search.
+    pub fn pv(&self) -> Line {
+        let mut line = Line::new();
+        for v in &self.pv[..self.pv_len.load(Ordering::Relaxed).min(MAX_PLY)] {
+            if let Some(d) = Self::decode(v.load(Ordering::Relaxed)) {
+                line.push(d);
+            }
+        }
+        line
+    }
+
+    /// Replaces the principal variation, which is followed first by the next search.
+    pub fn set_pv(&self, line: &[Direction]) {
+        let len = line.len().min(MAX_PLY);
+        for (v, &d) in self.pv.iter().zip(&line[..len]) {
+            v.store(d as u8 + 1, Ordering::Relaxed);
+        }
+        self.pv_len.store(len, Ordering::Relaxed);
+    }
+
+    /// Move of the principal variation, if the `path` from the root followed it.
+    fn pv_move(&self, path: &[Direction]) -> Option<Direction> {
+        let height = path.len();
+        if height >= self.pv_len.load(Ordering::Relaxed).min(MAX_PLY) {
+            return None;
+        }
+        let on_pv = path
+            .iter()
+            .zip(&self.pv)
+            .all(|(&d, v)| v.load(Ordering::Relaxed) == d as u8 + 1);
+        on_pv
+            .then(|| Self::decode(self.pv[height].load(Ordering::Relaxed)))
+            .flatten()
+    }
+
+    /// Valid moves of the snake in search order, starting with the `first` move.
+    ///
+    /// The `path` contains the moves from the root of the search to this ply.
+    /// If there are no valid moves, the snake has to move up (and dies).
+    pub fn order(
+        &self,
+        game: &Game,
+        snake: u8,
+        path: &[Direction],
+        first: Option<Direction>,
+    ) -> FixedVec<Direction, 4> {
+        let mut scored = FixedVec::<(u64, Direction), 4>::new();
+        for d in game.valid_moves(snake) {
+            let score = if Some(d) == first {
+                u64::MAX
+            } else if !self.enabled {
+                0
+            } else {
+                let height = path.len();
+                let killers = self.killers.get(height);
+                let is_killer =
+                    |i: usize| killers.is_some_and(|k| k[i].load(Ordering::Relaxed) == d as u8 + 1);
+                if self.pv_move(path) == Some(d) {
+                    u64::MAX - 1
+                } else if is_killer(0) {
+                    u64::MAX - 2
+                } else if is_killer(1) {
+                    u64::MAX - 3
+                } else {
+                    Self::history_index(game, snake, d)
+                        .map_or(0, |i| self.history[i].load(Ordering::Relaxed) as u64)
+                }
+            };
+            scored.push((score, d));
+        }
+        // Stable, so that equal scores keep the fixed order
+        scored.sort_by_key(|&(score, _)| Reverse(score));
+
+        let mut moves = FixedVec::new();
+        for &(_, d) in scored.iter() {
+            moves.push(d);
+        }
+        if moves.is_empty() {
+            moves.push(Direction::Up);
+        }
+        moves
+    }
+
+    /// Records the move `d` of the snake, which caused a cutoff after the `path`
+    /// with the remaining `depth`.
+    pub fn cutoff(&self, game: &Game, snake: u8, path: &[Direction], d: Direction, depth: usize) {
+        if !self.enabled {
+            return;
+        }
+        if let Some(killers) = self.killers.get(path.len()) {
+            let v = d as u8 + 1;
+            let first = killers[0].load(Ordering::Relaxed);
+            if first != v {
+                killers[1].store(first, Ordering::Relaxed);
+                killers[0].store(v, Ordering::Relaxed);
+            }
+        }
+        if let Some(i) = Self::history_index(game, snake, d) {
+            let bonus = (depth * depth).min(u16::MAX as usize) as u32;
+            self.history[i].fetch_add(bonus, Ordering::Relaxed);
+        }
+    }
+}
+
+impl Default for MoveOrdering {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl std::fmt::Debug for MoveOrdering {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("MoveOrdering")
+            .field("enabled", &self.enabled)
+            .field("pv", &&*self.pv())
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::logging;
+
+    #[test]
+    fn move_ordering() {
+        use super::*;
+        logging();
+
+        let game = Game::parse(
+            r#"
+            . . . . .
+            . . . . .
+            . . 0 . .
+            . . ^ . .
+            . . . . 1"#,
+        )
+        .unwrap();
+        use Direction::*;
+
+        let ordering = MoveOrdering::new();
+        assert_eq!(&*ordering.order(&game, 0, &[], None), &[Up, Right, Left]);
+        assert_eq!(
+            &*ordering.order(&game, 0, &[], Some(Left)),
+            &[Left, Up, Right]
+        );
+
+        // History
+        ordering.cutoff(&game, 0, &[Down], Right, 2);
+        assert_eq!(&*ordering.order(&game, 0, &[], None), &[Right, Up, Left]);
+
+        // Killers at the same ply are preferred
+        ordering.cutoff(&game, 0, &[], Left, 1);
+        assert_eq!(&*ordering.order(&game, 0, &[], None), &[Left, Right, Up]);
+        assert_eq!(&*ordering.order(&game, 0, &[Up], None), &[Right, Left, Up]);
+
+        // The principal variation is only followed along its path
+        ordering.set_pv(&[Up, Left, Up]);
+        assert_eq!(&*ordering.pv(), &[Up, Left, Up]);
+        assert_eq!(&*ordering.order(&game, 0, &[], None), &[Up, Left, Right]);
+        assert_eq!(
+            &*ordering.order(&game, 0, &[Up, Left], None),
+            &[Up, Right, Left]
+        );
+        assert_eq!(
+            &*ordering.order(&game, 0, &[Up, Down], None),
+            &[Right, Left, Up]
+        );
+
+        // Only the transposition table move is applied by the fixed ordering
+        let fixed = MoveOrdering::fixed();
+        fixed.cutoff(&game, 0, &[], Left, 1);
+        assert_eq!(&*fixed.order(&game, 0, &[], None), &[Up, Right, Left]);
+        assert_eq!(&*fixed.order(&game, 0, &[], Some(Left)), &[Left, Up, Right]);
+    }
+}
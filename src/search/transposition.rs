@@ -0,0 +1,137 @@
+use std::sync::Mutex;
+
+use crate::env::Direction;
+
+/// Default number of entries of the transposition table.
+pub const TT_CAPACITY: usize = 1 << 16;
+
+/// How the stored value relates to the real value of the state.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Bound {
+    /// The value is exact.
+    Exact,
+    /// The real value is at least the stored value (fail-high).
+    Lower,
+    /// The real value is at most the stored value (fail-low).
+    Upper,
+}
+
+/// Search result for a game state.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Entry {
+    /// Zobrist hash of the game state.
+    pub hash: u64,
+    /// Remaining search depth the value was computed with.
+    pub depth: usize,
+    pub value: f64,
+    /// Best move of the maximizing player.
+    pub best: Option<Direction>,
+    pub bound: Bound,
+}
+
+/// Bounded, thread-safe cache of search results, indexed by the Zobrist hash.
+///
+/// The table has a fixed number of slots and entries are overwritten on collisions,
+/// unless the slot already contains a deeper result for the same state.
+pub struct TranspositionTable {
+    slots: Vec<Mutex<Option<Entry>>>,
+    mask: usize,
+}
+
+impl TranspositionTable {
+    /// Creates a table with `capacity` slots, rounded up to a power of two.
+    pub fn new(capacity: usize) -> Self {
+        let capacity = capacity.max(1).next_power_of_two();
+        Self {
+            slots: (0..capacity).map(|_| Mutex::new(None)).collect(),
+            mask: capacity - 1,
+        }
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.slots.len()
+    }
+
+    fn slot(&self, hash: u64) -> &Mutex<Option<Entry>> {
+        &self.slots[hash as usize & self.mask]
+    }
+
+    /// Returns the entry of the given state, if it is stored.
+    pub fn get(&self, hash: u64) -> Option<Entry> {
+        let entry = (*self.slot(hash).lock().ok()?)?;
+        (entry.hash == hash).then_some(entry)
+    }
+
+    /// Stores the entry, if the slot does not contain a deeper result for the same state.
+    pub fn insert(&self, entry: Entry) {
+        if let Ok(mut slot) = self.slot(entry.hash).lock() {
+            match *slot {
+                Some(old) if old.hash == entry.hash && old.depth > entry.depth => {}
+                _ => *slot = Some(entry),
+            }
+        }
+    }
+
+    /// Removes all entries.
+    pub fn clear(&self) {
+        for slot in &self.slots {
+            if let Ok(mut slot) = slot.lock() {
+                *slot = None;
+            }
+        }
+    }
+}
+
+impl Default for TranspositionTable {
+    fn default() -> Self {
+        Self::new(TT_CAPACITY)
+    }
+}
+
+impl std::fmt::Debug for TranspositionTable {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("TranspositionTable")
+            .field("capacity", &self.capacity())
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::logging;
+
+    #[test]
+    fn transposition_table() {
+        use super::*;
+        logging();
+
+        let tt = TranspositionTable::new(1000);
+        assert_eq!(tt.capacity(), 1024);
+
+        let entry = |hash, depth, value| Entry {
+            hash,
+            depth,
+            value,
+            best: Some(Direction::Left),
+            bound: Bound::Exact,
+        };
+
+        assert_eq!(tt.get(7), None);
+        tt.insert(entry(7, 3, 1.0));
+        assert_eq!(tt.get(7), Some(entry(7, 3, 1.0)));
+
+        // Shallower results for the same state are ignored
+        tt.insert(entry(7, 2, 2.0));
+        assert_eq!(tt.get(7).unwrap().value, 1.0);
+        tt.insert(entry(7, 4, 3.0));
+        assert_eq!(tt.get(7).unwrap().value, 3.0);
+
+        // Other states in the same slot are replaced
+        tt.insert(entry(7 + 1024, 1, 4.0));
+        assert_eq!(tt.get(7), None);
+        assert_eq!(tt.get(7 + 1024).unwrap().value, 4.0);
+
+        tt.clear();
+        assert_eq!(tt.get(7 + 1024), None);
+    }
+}
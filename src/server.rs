@@ -0,0 +1,705 @@
+//! Routes and runtime state of the battlesnake server.
+
+use std::collections::HashMap;
+use std::convert::Infallible;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, Instant, SystemTime};
+
+use log::{info, warn};
+use serde::Deserialize;
+use tokio::time;
+use warp::http::StatusCode;
+use warp::{Filter, Rejection, Reply};
+
+use crate::agents::{Agent, Setup};
+use crate::env::{GameRequest, IndexResponse, API_VERSION};
+use crate::latency::LatencyConfig;
+use crate::metrics::{self, METRICS};
+use crate::savegame::Recorder;
+use crate::session::SessionStore;
+
+pub const PACKAGE_VERSION: &str = env!("CARGO_PKG_VERSION");
+pub const AUTHOR: &str = "l4r0x";
+
+/// Path segments that cannot be used as snake names.
+const RESERVED: [&str; 5] = ["start", "move", "end", "metrics", "admin"];
+
+/// Interval in which the `--snakes` file is checked for changes.
+const WATCH_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Snake that is served by the server.
+#[derive(Clone)]
+pub struct Snake {
+    pub color: String,
+    pub head: String,
+    pub tail: String,
+    pub config: Arc<Agent>,
+}
+
+impl Snake {
+    /// Creates the snake, using the appearance of the `default` snake for missing values.
+    pub fn new(config: SnakeConfig, default: &Snake) -> Self {
+        Self {
+            color: config.color.unwrap_or_else(|| default.color.clone()),
+            head: config.head.unwrap_or_else(|| default.head.clone()),
+            tail: config.tail.unwrap_or_else(|| default.tail.clone()),
+            config: Arc::new(config.config),
+        }
+    }
+
+    /// Agent that plays the game, which `Auto` configs select by the request.
+    pub fn agent(&self, request: &GameRequest) -> Arc<Agent> {
+        match &*self.config {
+            Agent::Auto(_) => Arc::new(self.config.select(&Setup::from_request(request)).clone()),
+            _ => self.config.clone(),
+        }
+    }
+}
+
+/// Snake of the `--snakes` file.
+///
+/// The appearance defaults to the commandline options.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct SnakeConfig {
+    pub config: Agent,
+    pub color: Option<String>,
+    pub head: Option<String>,
+    pub tail: Option<String>,
+}
+
+/// Runtime server configuration.
+pub struct State {
+    pub latency: LatencyConfig,
+    /// Snakes that are served at `/<name>/`, and the default snake with the empty name at `/`.
+    ///
+    /// The snakes are replaced at runtime by the admin API and the file watcher.
+    pub snakes: RwLock<HashMap<String, Arc<Snake>>>,
+    /// State of the running games.
+    pub sessions: SessionStore,
+    pub recorder: Option<Recorder>,
+    /// Bearer token of the admin API, which is disabled without it.
+    pub admin_token: Option<String>,
+}
+
+impl State {
+    pub fn snake(&self, name: &str) -> Option<Arc<Snake>> {
+        self.snakes.read().unwrap().get(name).cloned()
+    }
+
+    /// Replaces the agent of a snake, which is used by the games started afterwards.
+    pub fn set_config(&self, name: &str, config: Agent) -> Option<Arc<Snake>> {
+        let mut snakes = self.snakes.write().unwrap();
+        let snake = snakes.get_mut(name)?;
+        *snake = Arc::new(Snake {
+            config: Arc::new(config),
+            ..Snake::clone(snake)
+        });
+        info!("config of {name:?}: {}", snake.config);
+        Some(snake.clone())
+    }
+
+    /// Updates and adds the snakes of the `--snakes` file.
+    pub fn reload(&self, configs: HashMap<String, SnakeConfig>) {
+        let mut snakes = self.snakes.write().unwrap();
+        let default = snakes[""].clone();
+        for (name, config) in configs {
+            let snake = Snake::new(config, &default);
+            info!("config of {name:?}: {}", snake.config);
+            snakes.insert(name, Arc::new(snake));
+        }
+    }
+
+    /// Reloads the snakes if the file was modified since the `last` check.
+    ///
+    /// Returns if the snakes were reloaded.
+    fn reload_modified(&self, path: &Path, last: &mut Option<SystemTime>) -> bool {
+        let current = modified(path);
+        if current == *last {
+            return false;
+        }
+        *last = current;
+        match load_snakes(path) {
+            Ok(configs) => {
+                self.reload(configs);
+                true
+            }
+            Err(e) => {
+                warn!("reloading {path:?} failed: {e}");
+                false
+            }
+        }
+    }
+}
+
+fn modified(path: &Path) -> Option<SystemTime> {
+    path.metadata().ok()?.modified().ok()
+}
+
+pub fn load_snakes(path: &Path) -> Result<HashMap<String, SnakeConfig>, String> {
+    let data = std::fs::read_to_string(path).map_err(|e| e.to_string())?;
+    let snakes: HashMap<String, SnakeConfig> =
+        serde_json::from_str(&data).map_err(|e| e.to_string())?;
+    for name in snakes.keys() {
+        if name.is_empty() || name.contains('/') || RESERVED.contains(&name.as_str()) {
+            return Err(format!("invalid snake name {name:?}"));
+        }
+    }
+    Ok(snakes)
+}
+
+/// All routes of the server: the snakes, the metrics, and the admin API.
+pub fn routes(
+    state: Arc<State>,
+) -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone {
+    // The method is checked last, so that unknown paths are not found
+    let index = with_snake(state.clone())
+        .and(warp::path::end())
+        .and(warp::get())
+        .map(|snake: Arc<Snake>| {
+            warn!("index");
+            warp::reply::json(&IndexResponse::new(
+                API_VERSION.into(),
+                AUTHOR.into(),
+                snake.color.clone().into(),
+                snake.head.clone().into(),
+                snake.tail.clone().into(),
+                PACKAGE_VERSION.into(),
+            ))
+        });
+
+    let start = with_snake(state.clone())
+        .and(warp::path("start"))
+        .and(warp::post())
+        .and(warp::body::json::<GameRequest>())
+        .and(with_state(state.clone()))
+        .and_then(start);
+
+    let r#move = with_snake(state.clone())
+        .and(warp::path("move"))
+        .and(warp::post())
+        .and(warp::body::json::<GameRequest>())
+        .and(with_state(state.clone()))
+        .and_then(step);
+
+    let end = with_snake(state.clone())
+        .and(warp::path("end"))
+        .and(warp::post())
+        .and(warp::body::json::<GameRequest>())
+        .and(with_state(state.clone()))
+        .and_then(end);
+
+    let admin = warp::path("admin")
+        .and(warp::path("config"))
+        .and(
+            warp::path::param::<String>()
+                .or(warp::any().map(String::new))
+                .unify(),
+        )
+        .and(warp::path::end())
+        .and(with_admin(state.clone()));
+    let get_config = admin
+        .clone()
+        .and(warp::get())
+        .and(with_state(state.clone()))
+        .and_then(get_config);
+    let put_config = admin
+        .and(warp::put())
+        .and(warp::body::json::<Agent>())
+        .and(with_state(state.clone()))
+        .and_then(put_config);
+
+    index
+        .or(start)
+        .or(r#move)
+        .or(end)
+        .or(metrics::route())
+        .or(get_config)
+        .or(put_config)
+        .recover(unauthorized)
+}
+
+/// Reloads the snakes when the file has been modified.
+pub async fn watch_snakes(path: PathBuf, state: Arc<State>) {
+    let mut last = modified(&path);
+    let mut interval = time::interval(WATCH_INTERVAL);
+    loop {
+        interval.tick().await;
+        state.reload_modified(&path, &mut last);
+    }
+}
+
+fn with_state(
+    config: Arc<State>,
+) -> impl Filter<Extract = (Arc<State>,), Error = std::convert::Infallible> + Clone {
+    warp::any().map(move || config.clone())
+}
+
+/// Selects the snake by the first path segment, or the default snake.
+fn with_snake(
+    state: Arc<State>,
+) -> impl Filter<Extract = (Arc<Snake>,), Error = Rejection> + Clone {
+    let named = {
+        let state = state.clone();
+        warp::path::param::<String>().and_then(move |name: String| {
+            let snake = state.snake(&name);
+            async move { snake.ok_or_else(warp::reject::not_found) }
+        })
+    };
+    let default = warp::any().and_then(move || {
+        let snake = state.snake("");
+        async move { snake.ok_or_else(warp::reject::not_found) }
+    });
+    named.or(default).unify()
+}
+
+#[derive(Debug)]
+struct Unauthorized;
+
+impl warp::reject::Reject for Unauthorized {}
+
+/// Rejects requests without the admin token.
+fn with_admin(state: Arc<State>) -> impl Filter<Extract = (), Error = Rejection> + Clone {
+    warp::header::optional::<String>("authorization")
+        .and_then(move |auth: Option<String>| {
+            let authorized = match (&state.admin_token, auth) {
+                (Some(token), Some(auth)) => auth
+                    .strip_prefix("Bearer ")
+                    .is_some_and(|auth| constant_time_eq(auth.as_bytes(), token.as_bytes())),
+                _ => false,
+            };
+            async move {
+                if authorized {
+                    Ok(())
+                } else {
+                    Err(warp::reject::custom(Unauthorized))
+                }
+            }
+        })
+        .untuple_one()
+}
+
+/// Compares the bytes in a time that only depends on their lengths,
+/// so that the token cannot be guessed from the response times.
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |diff, (a, b)| diff | (a ^ b)) == 0
+}
+
+async fn unauthorized(rejection: Rejection) -> Result<impl Reply, Rejection> {
+    if rejection.find::<Unauthorized>().is_some() {
+        Ok(StatusCode::UNAUTHORIZED)
+    } else {
+        Err(rejection)
+    }
+}
+
+/// Returns the config of the snake as JSON.
+fn config_reply(snake: Option<Arc<Snake>>) -> warp::reply::Response {
+    match snake {
+        Some(snake) => {
+            warp::reply::with_header(snake.config.to_string(), "content-type", "application/json")
+                .into_response()
+        }
+        None => StatusCode::NOT_FOUND.into_response(),
+    }
+}
+
+async fn get_config(name: String, state: Arc<State>) -> Result<impl Reply, Infallible> {
+    Ok(config_reply(state.snake(&name)))
+}
+
+async fn put_config(
+    name: String,
+    config: Agent,
+    state: Arc<State>,
+) -> Result<impl Reply, Infallible> {
+    warn!("set config {name:?}");
+    Ok(config_reply(state.set_config(&name, config)))
+}
+
+async fn start(
+    snake: Arc<Snake>,
+    request: GameRequest,
+    state: Arc<State>,
+) -> Result<impl warp::Reply, Infallible> {
+    warn!("start {request}");
+    state.sessions.start(&request, snake.agent(&request));
+    METRICS.active_games.set(state.sessions.len() as _);
+
+    if let Some(recorder) = &state.recorder {
+        if let Err(e) = recorder.start(&request).await {
+            warn!("recording failed: {e}");
+        }
+    }
+    Ok(warp::reply())
+}
+
+async fn step(
+    snake: Arc<Snake>,
+    request: GameRequest,
+    state: Arc<State>,
+) -> Result<impl warp::Reply, Infallible> {
+    warn!("move {request}");
+
+    let timer = Instant::now();
+    let mut session = state.sessions.take(&request);
+    session.latency.arrive(&request, timer);
+    let latency = session.latency.latency(&state.latency);
+    info!("latency {latency}ms");
+    // Games keep the agent they were started with
+    let agent = session
+        .agent
+        .get_or_insert_with(|| snake.agent(&request))
+        .clone();
+    let next_move = agent.step_session(&request, latency, &mut session).await;
+    let time = timer.elapsed();
+    info!("response time {:?}ms", time.as_millis());
+    session.latency.respond(time);
+    state.sessions.put(&request, session);
+
+    METRICS.move_latency.observe(time.as_secs_f64());
+    if time.as_millis() > request.game.timeout as u128 {
+        METRICS.timeouts.inc();
+    }
+    METRICS.agent_moves.inc(agent.name());
+    METRICS.active_games.set(state.sessions.len() as _);
+
+    if let Some(recorder) = &state.recorder {
+        if let Err(e) = recorder.record_move(&request, next_move.r#move, time).await {
+            warn!("recording failed: {e}");
+        }
+    }
+    Ok(warp::reply::json(&next_move))
+}
+
+async fn end(
+    _snake: Arc<Snake>,
+    request: GameRequest,
+    state: Arc<State>,
+) -> Result<impl warp::Reply, Infallible> {
+    warn!("end {request}");
+    state.sessions.end(&request);
+    METRICS.active_games.set(state.sessions.len() as _);
+
+    if let Some(recorder) = &state.recorder {
+        match recorder.end(&request).await {
+            Ok(result) => info!("recorded {result:?} {:?}", recorder.path(&request)),
+            Err(e) => warn!("recording failed: {e}"),
+        }
+    }
+    Ok(warp::reply())
+}
+
+#[cfg(test)]
+mod test {
+    use crate::logging;
+
+    fn state(admin_token: Option<&str>) -> std::sync::Arc<super::State> {
+        use super::*;
+        let snake = |color: &str, config: &str| {
+            Arc::new(Snake {
+                color: color.into(),
+                head: "default".into(),
+                tail: "default".into(),
+                config: Arc::new(config.parse().unwrap()),
+            })
+        };
+        Arc::new(State {
+            latency: LatencyConfig::fixed(0),
+            snakes: RwLock::new(HashMap::from([
+                (String::new(), snake("#000000", r#"{"Random":null}"#)),
+                ("alpha".into(), snake("#ff0000", r#"{"Random":null}"#)),
+            ])),
+            sessions: SessionStore::default(),
+            recorder: None,
+            admin_token: admin_token.map(Into::into),
+        })
+    }
+
+    fn request(game: &str, turn: usize, snakes: usize) -> String {
+        let snake = |i: usize| {
+            format!(
+                r#"{{"id":"{i}","name":"{i}","health":90,"body":[{{"x":{x},"y":1}},{{"x":{x},"y":0}}]}}"#,
+                x = 2 * i + 1
+            )
+        };
+        let board = (0..snakes).map(snake).collect::<Vec<_>>().join(",");
+        format!(
+            r#"{{"game":{{"id":"{game}","ruleset":{{"name":"standard"}},"timeout":50}},"turn":{turn},"board":{{"width":11,"height":11,"food":[],"hazards":[],"snakes":[{board}]}},"you":{}}}"#,
+            snake(0)
+        )
+    }
+
+    fn temp_file(name: &str, content: &str) -> std::path::PathBuf {
+        let path = std::env::temp_dir().join(format!("snork-{}-{name}", std::process::id()));
+        std::fs::write(&path, content).unwrap();
+        path
+    }
+
+    #[tokio::test]
+    async fn server_routing() {
+        use super::*;
+        logging();
+
+        let routes = routes(state(None));
+        let color = |response: warp::http::Response<warp::hyper::body::Bytes>| {
+            assert_eq!(response.status(), 200);
+            let index: serde_json::Value = serde_json::from_slice(response.body()).unwrap();
+            index["color"].as_str().unwrap().to_string()
+        };
+
+        // The root path serves the default snake
+        let response = warp::test::request().path("/").reply(&routes).await;
+        assert_eq!(color(response), "#000000");
+        let response = warp::test::request()
+            .method("POST")
+            .path("/move")
+            .body(request("g", 0, 2))
+            .reply(&routes)
+            .await;
+        assert_eq!(response.status(), 200);
+
+        // Named snakes are served at their path
+        let response = warp::test::request().path("/alpha").reply(&routes).await;
+        assert_eq!(color(response), "#ff0000");
+        for path in ["/alpha/start", "/alpha/move", "/alpha/end"] {
+            let response = warp::test::request()
+                .method("POST")
+                .path(path)
+                .body(request("h", 0, 2))
+                .reply(&routes)
+                .await;
+            assert_eq!(response.status(), 200, "{path}");
+        }
+
+        // Unknown snakes are not found
+        let response = warp::test::request().path("/nope").reply(&routes).await;
+        assert_eq!(response.status(), 404);
+        let response = warp::test::request()
+            .method("POST")
+            .path("/nope/move")
+            .body(request("g", 0, 2))
+            .reply(&routes)
+            .await;
+        assert_eq!(response.status(), 404);
+
+        // The metrics are not shadowed by the snakes
+        let response = warp::test::request().path("/metrics").reply(&routes).await;
+        assert_eq!(response.status(), 200);
+    }
+
+    #[tokio::test]
+    async fn server_admin() {
+        use super::*;
+        logging();
+
+        let admin = |method: &str, path: &str, auth: Option<&str>| {
+            let request = warp::test::request().method(method).path(path);
+            match auth {
+                Some(auth) => request.header("authorization", auth),
+                None => request,
+            }
+        };
+
+        // Disabled without a token
+        let routes = routes(state(None));
+        let response = admin("GET", "/admin/config", Some("Bearer "))
+            .reply(&routes)
+            .await;
+        assert_eq!(response.status(), 401);
+
+        let state = state(Some("secret"));
+        let routes = super::routes(state.clone());
+        for auth in [
+            None,
+            Some("Bearer nope"),
+            Some("secret"),
+            Some("Bearer secret2"),
+        ] {
+            let response = admin("GET", "/admin/config", auth).reply(&routes).await;
+            assert_eq!(response.status(), 401, "{auth:?}");
+            let response = admin("PUT", "/admin/config/alpha", auth)
+                .body(r#"{"Flood":{}}"#)
+                .reply(&routes)
+                .await;
+            assert_eq!(response.status(), 401, "{auth:?}");
+        }
+        assert!(matches!(
+            *state.snake("alpha").unwrap().config,
+            Agent::Random(_)
+        ));
+
+        let auth = Some("Bearer secret");
+        let response = admin("GET", "/admin/config", auth).reply(&routes).await;
+        assert_eq!(response.status(), 200);
+        assert_eq!(response.headers()["content-type"], "application/json");
+        assert_eq!(response.body(), r#"{"Random":null}"#);
+        let response = admin("GET", "/admin/config/nope", auth)
+            .reply(&routes)
+            .await;
+        assert_eq!(response.status(), 404);
+
+        // Running games keep their agent
+        let game = request("g", 0, 2);
+        let response = warp::test::request()
+            .method("POST")
+            .path("/alpha/start")
+            .body(&game)
+            .reply(&routes)
+            .await;
+        assert_eq!(response.status(), 200);
+
+        let response = admin("PUT", "/admin/config/alpha", auth)
+            .body(r#"{"Flood":{"space":3.0}}"#)
+            .reply(&routes)
+            .await;
+        assert_eq!(response.status(), 200);
+        let response = admin("GET", "/admin/config/alpha", auth)
+            .reply(&routes)
+            .await;
+        let config: Agent = std::str::from_utf8(response.body())
+            .unwrap()
+            .parse()
+            .unwrap();
+        assert!(matches!(config, Agent::Flood(_)));
+        assert!(matches!(
+            *state.snake("alpha").unwrap().config,
+            Agent::Flood(_)
+        ));
+        assert!(matches!(*state.snake("").unwrap().config, Agent::Random(_)));
+
+        let game: GameRequest = serde_json::from_str(&game).unwrap();
+        let session = state.sessions.take(&game);
+        assert!(matches!(
+            **session.agent.as_ref().unwrap(),
+            Agent::Random(_)
+        ));
+
+        // Unknown snakes and invalid configs
+        let response = admin("PUT", "/admin/config/nope", auth)
+            .body(r#"{"Flood":{}}"#)
+            .reply(&routes)
+            .await;
+        assert_eq!(response.status(), 404);
+        let response = admin("PUT", "/admin/config/alpha", auth)
+            .body(r#"{"Unknown":{}}"#)
+            .reply(&routes)
+            .await;
+        assert_eq!(response.status(), 400);
+
+        assert!(constant_time_eq(b"secret", b"secret"));
+        assert!(!constant_time_eq(b"secret", b"secreT"));
+        assert!(!constant_time_eq(b"secret", b"secrets"));
+    }
+
+    #[test]
+    fn server_watch_snakes() {
+        use super::*;
+        logging();
+
+        let state = state(None);
+        let path = temp_file("watch.json", r#"{"beta":{"config":{"Random":null}}}"#);
+        let mut last = None;
+        assert!(state.reload_modified(&path, &mut last));
+        assert!(!state.reload_modified(&path, &mut last));
+        assert_eq!(state.snake("beta").unwrap().color, "#000000");
+
+        // Changed snakes are reloaded, others are kept
+        let changed = r##"{"beta":{"config":{"Flood":{}},"color":"#0000ff"}}"##;
+        std::fs::write(&path, changed).unwrap();
+        let later = last.unwrap() + Duration::from_secs(1);
+        std::fs::File::options()
+            .write(true)
+            .open(&path)
+            .unwrap()
+            .set_modified(later)
+            .unwrap();
+        assert!(state.reload_modified(&path, &mut last));
+        let beta = state.snake("beta").unwrap();
+        assert_eq!(beta.color, "#0000ff");
+        assert!(matches!(*beta.config, Agent::Flood(_)));
+        assert!(state.snake("alpha").is_some());
+
+        // Invalid files are ignored
+        std::fs::write(&path, r#"{"move":{"config":{"Random":null}}}"#).unwrap();
+        let later = later + Duration::from_secs(1);
+        std::fs::File::options()
+            .write(true)
+            .open(&path)
+            .unwrap()
+            .set_modified(later)
+            .unwrap();
+        assert!(!state.reload_modified(&path, &mut last));
+        assert!(state.snake("move").is_none());
+        assert_eq!(state.snake("beta").unwrap().color, "#0000ff");
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn server_auto_agent() {
+        use super::*;
+        logging();
+
+        let state = state(None);
+        let config = r#"{"Auto":{"rules":[{"max_snakes":1,"agent":{"Solo":{}}}],"default":{"Random":null}}}"#;
+        let auto = Snake {
+            config: Arc::new(config.parse().unwrap()),
+            ..Snake::clone(&state.snake("").unwrap())
+        };
+        state
+            .snakes
+            .write()
+            .unwrap()
+            .insert("auto".into(), Arc::new(auto));
+        let routes = routes(state.clone());
+        let post = |path: &'static str, body: String| {
+            warp::test::request()
+                .method("POST")
+                .path(path)
+                .body(body)
+                .reply(&routes)
+        };
+        let agent = |game: &str| {
+            let request = serde_json::from_str(&request(game, 0, 1)).unwrap();
+            let session = state.sessions.take(&request);
+            session.agent.unwrap()
+        };
+
+        // Games keep the agent of the start, after the other snakes are eliminated
+        assert_eq!(post("/auto/start", request("g", 0, 2)).await.status(), 200);
+        assert_eq!(post("/auto/move", request("g", 5, 1)).await.status(), 200);
+        assert!(matches!(*agent("g"), Agent::Random(_)));
+
+        // Without a start, the agent is only selected by the snakes on the first turn
+        assert_eq!(post("/auto/move", request("h", 5, 1)).await.status(), 200);
+        assert!(matches!(*agent("h"), Agent::Random(_)));
+        assert_eq!(post("/auto/move", request("i", 0, 1)).await.status(), 200);
+        assert!(matches!(*agent("i"), Agent::Solo(_)));
+    }
+
+    #[test]
+    fn server_load_snakes() {
+        use super::*;
+        logging();
+
+        let path = temp_file(
+            "snakes.json",
+            r##"{"beta":{"config":{"Random":null},"color":"#00ff00"}}"##,
+        );
+        let snakes = load_snakes(&path).unwrap();
+        assert_eq!(snakes["beta"].color.as_deref(), Some("#00ff00"));
+        assert!(snakes["beta"].head.is_none());
+
+        // Reserved and empty names are rejected
+        for name in RESERVED.iter().chain(&["", "a/b"]) {
+            std::fs::write(
+                &path,
+                format!(r#"{{"{name}":{{"config":{{"Random":null}}}}}}"#),
+            )
+            .unwrap();
+            assert!(load_snakes(&path).is_err(), "{name}");
+        }
+        std::fs::remove_file(path).unwrap();
+    }
+}
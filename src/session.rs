@@ -0,0 +1,353 @@
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use log::info;
+
+use crate::agents::maxn::Ponder;
+use crate::agents::Agent;
+use crate::env::{Direction, GameRequest};
+use crate::game::Game;
+use crate::latency::LatencyEstimator;
+
+/// Number of previous requests that are kept per session.
+pub const HISTORY_LEN: usize = 32;
+
+/// Default time after which sessions without requests are evicted.
+pub const SESSION_TTL: Duration = Duration::from_secs(600);
+
+/// State of a game of one of our snakes, which is kept between the requests.
+#[derive(Default)]
+pub struct Session {
+    /// Previous requests, the current one last
+    pub history: VecDeque<GameRequest>,
+    /// How often the opponents chose each move, by their snake id
+    pub opponent_moves: HashMap<String, [u32; 4]>,
+    /// If the tree search agents continue searching between the turns
+    pub pondering: bool,
+    /// Search that continues between the turns
+    pub ponder: Option<Ponder>,
+    /// Agent that plays the game, which is kept if the server config changes
+    pub agent: Option<Arc<Agent>>,
+    /// Latency between the game engine and the server
+    pub latency: LatencyEstimator,
+}
+
+impl Session {
+    pub fn new(pondering: bool) -> Self {
+        Self {
+            pondering,
+            ..Self::default()
+        }
+    }
+
+    /// Records the request of a new turn and the moves of the opponents
+    /// since the previous request.
+    ///
+    /// Repeated requests of the same turn are ignored.
+    pub fn update(&mut self, request: &GameRequest, game: &Game) {
+        if let Some(prev) = self.history.back() {
+            if prev.turn >= request.turn {
+                return;
+            }
+            for snake in &request.board.snakes {
+                if snake.id == request.you.id {
+                    continue;
+                }
+                let prev_head = prev.board.snakes.iter().find(|s| s.id == snake.id);
+                let (Some(&prev), Some(&head)) =
+                    (prev_head.and_then(|s| s.body.first()), snake.body.first())
+                else {
+                    continue;
+                };
+                if let Some(d) =
+                    Direction::iter().find(|&d| game.grid.neighbor(prev, d) == Some(head))
+                {
+                    self.opponent_moves.entry(snake.id.clone()).or_default()[d as usize] += 1;
+                }
+            }
+        }
+
+        if self.history.len() >= HISTORY_LEN {
+            self.history.pop_front();
+        }
+        self.history.push_back(request.clone());
+    }
+
+    /// Returns the most frequent move of an opponent.
+    pub fn likely_move(&self, id: &str) -> Option<Direction> {
+        let moves = self.opponent_moves.get(id)?;
+        (0..4)
+            .max_by_key(|&d| moves[d])
+            .map(|d| Direction::from(d as u8))
+    }
+
+    /// The most frequent moves of all snakes in the order of `Game::from_request`.
+    pub fn likely_moves(&self, request: &GameRequest) -> Vec<Option<Direction>> {
+        let others = request
+            .board
+            .snakes
+            .iter()
+            .filter(|s| s.id != request.you.id);
+        std::iter::once(&request.you)
+            .chain(others)
+            .map(|s| self.likely_move(&s.id))
+            .collect()
+    }
+}
+
+/// Identifies a session by the game id and our snake id.
+pub type SessionKey = (String, String);
+
+/// Thread-safe store of the sessions of the running games.
+///
+/// Sessions are created on `/start` (or the first move) and dropped on `/end`.
+/// Sessions that have not been accessed for the `ttl` are evicted,
+/// because some games never send `/end`.
+pub struct SessionStore {
+    sessions: Mutex<HashMap<SessionKey, Entry>>,
+    ttl: Duration,
+    pondering: bool,
+}
+
+/// Stored session of a game.
+struct Entry {
+    access: Instant,
+    /// The session, which is `None` while it is taken by a move
+    session: Option<Session>,
+    /// If the game ended while the session was taken
+    ended: bool,
+}
+
+impl Entry {
+    fn new(session: Option<Session>) -> Self {
+        Self {
+            access: Instant::now(),
+            session,
+            ended: false,
+        }
+    }
+}
+
+impl SessionStore {
+    pub fn new(ttl: Duration, pondering: bool) -> Self {
+        Self {
+            sessions: Mutex::new(HashMap::new()),
+            ttl,
+            pondering,
+        }
+    }
+
+    pub fn key(request: &GameRequest) -> SessionKey {
+        (request.game.id.clone(), request.you.id.clone())
+    }
+
+    /// Creates a new session for the game that is played by the `agent`,
+    /// replacing an existing one.
+    pub fn start(&self, request: &GameRequest, agent: Arc<Agent>) {
+        let mut session = Session::new(self.pondering);
+        session.agent = Some(agent);
+        let mut sessions = self.sessions.lock().unwrap();
+        self.evict(&mut sessions);
+        sessions.insert(Self::key(request), Entry::new(Some(session)));
+    }
+
+    /// Takes the session of the game for the duration of a move,
+    /// until it is returned with `put`.
+    ///
+    /// If there is no session, because `/start` was missed, a new one is created.
+    pub fn take(&self, request: &GameRequest) -> Session {
+        let mut sessions = self.sessions.lock().unwrap();
+        self.evict(&mut sessions);
+        let entry = sessions
+            .entry(Self::key(request))
+            .or_insert_with(|| Entry::new(None));
+        entry.access = Instant::now();
+        entry
+            .session
+            .take()
+            .unwrap_or_else(|| Session::new(self.pondering))
+    }
+
+    /// Returns the session of the game after a move.
+    ///
+    /// The session is dropped, if the game ended or was restarted in the meantime.
+    pub fn put(&self, request: &GameRequest, session: Session) {
+        let mut sessions = self.sessions.lock().unwrap();
+        self.evict(&mut sessions);
+        let key = Self::key(request);
+        match sessions.get_mut(&key) {
+            Some(entry) if entry.ended => {
+                sessions.remove(&key);
+            }
+            Some(Entry {
+                session: Some(_), ..
+            }) => {}
+            Some(entry) => {
+                entry.access = Instant::now();
+                entry.session = Some(session);
+            }
+            None => {
+                sessions.insert(key, Entry::new(Some(session)));
+            }
+        }
+    }
+
+    /// Drops the session of the game, which also stops its searches.
+    ///
+    /// Sessions that are taken by a move are dropped when they are returned.
+    pub fn end(&self, request: &GameRequest) -> Option<Session> {
+        let mut sessions = self.sessions.lock().unwrap();
+        self.evict(&mut sessions);
+        let key = Self::key(request);
+        let entry = sessions.get_mut(&key)?;
+        if entry.session.is_none() {
+            entry.ended = true;
+            return None;
+        }
+        sessions.remove(&key).and_then(|e| e.session)
+    }
+
+    /// Number of running games.
+    pub fn len(&self) -> usize {
+        let sessions = self.sessions.lock().unwrap();
+        sessions.values().filter(|e| !e.ended).count()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    fn evict(&self, sessions: &mut HashMap<SessionKey, Entry>) {
+        sessions.retain(|(game, snake), entry| {
+            let keep = entry.access.elapsed() < self.ttl;
+            if !keep {
+                info!("evict session {game} {snake}");
+            }
+            keep
+        });
+    }
+}
+
+impl Default for SessionStore {
+    fn default() -> Self {
+        Self::new(SESSION_TTL, false)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::logging;
+
+    fn request(game: &str, turn: usize, heads: [(i16, i16); 2]) -> super::GameRequest {
+        let snake = |id: &str, (x, y): (i16, i16)| {
+            format!(
+                r#"{{"id":"{id}","name":"{id}","health":90,"body":[{{"x":{x},"y":{y}}},{{"x":{x},"y":{y}}},{{"x":{x},"y":{y}}}]}}"#
+            )
+        };
+        let you = snake("a", heads[0]);
+        serde_json::from_str(&format!(
+            r#"{{"game":{{"id":"{game}","ruleset":{{"name":"standard"}},"timeout":500}},"turn":{turn},"board":{{"width":11,"height":11,"food":[],"hazards":[],"snakes":[{you},{}]}},"you":{you}}}"#,
+            snake("b", heads[1])
+        ))
+        .unwrap()
+    }
+
+    #[test]
+    fn session_history() {
+        use super::*;
+        logging();
+
+        let mut session = Session::default();
+        let turns = [
+            [(1, 1), (5, 5)],
+            [(1, 2), (5, 6)],
+            [(1, 3), (6, 6)],
+            [(1, 4), (7, 6)],
+        ];
+        for (turn, heads) in turns.into_iter().enumerate() {
+            let request = request("g", turn, heads);
+            session.update(&request, &Game::from_request(&request));
+        }
+        assert_eq!(session.history.len(), 4);
+        assert_eq!(session.history.back().unwrap().turn, 3);
+        assert_eq!(session.opponent_moves["b"], [1, 2, 0, 0]);
+
+        // Repeated requests are not counted twice
+        let repeated = request("g", 3, [(1, 4), (7, 6)]);
+        session.update(&repeated, &Game::from_request(&repeated));
+        assert_eq!(session.history.len(), 4);
+        assert_eq!(session.opponent_moves["b"], [1, 2, 0, 0]);
+        assert_eq!(session.likely_move("b"), Some(Direction::Right));
+        assert_eq!(session.likely_move("a"), None);
+
+        for turn in 4..4 + HISTORY_LEN {
+            let request = request("g", turn, [(1, 4), (7, 6)]);
+            session.update(&request, &Game::from_request(&request));
+        }
+        assert_eq!(session.history.len(), HISTORY_LEN);
+        assert_eq!(session.history.front().unwrap().turn, 4);
+    }
+
+    #[test]
+    fn session_store() {
+        use super::*;
+        logging();
+
+        let store = SessionStore::new(Duration::from_millis(50), false);
+        let game = request("g", 0, [(1, 1), (5, 5)]);
+        let other = request("h", 0, [(1, 1), (5, 5)]);
+
+        let agent = Arc::new(Agent::default());
+        store.start(&game, agent.clone());
+        let mut session = store.take(&game);
+        assert_eq!(store.len(), 1);
+        assert!(Arc::ptr_eq(session.agent.as_ref().unwrap(), &agent));
+        session.update(&game, &Game::from_request(&game));
+        store.put(&game, session);
+        store.start(&other, agent.clone());
+        assert_eq!(store.len(), 2);
+
+        assert_eq!(store.end(&game).unwrap().history.len(), 1);
+        assert!(store.end(&game).is_none());
+        assert_eq!(store.len(), 1);
+
+        // Games without `/end` are evicted
+        std::thread::sleep(Duration::from_millis(60));
+        store.start(&game, agent);
+        assert_eq!(store.len(), 1);
+        assert!(store.end(&other).is_none());
+
+        // Sessions without `/start` have no agent yet
+        assert!(store.take(&other).agent.is_none());
+    }
+
+    #[test]
+    fn session_store_end_during_move() {
+        use super::*;
+        logging();
+
+        let store = SessionStore::default();
+        let game = request("g", 0, [(1, 1), (5, 5)]);
+        let agent = Arc::new(Agent::default());
+
+        // The game ends, while the move is still running
+        store.start(&game, agent.clone());
+        let session = store.take(&game);
+        assert!(store.end(&game).is_none());
+        assert!(store.is_empty());
+        store.put(&game, session);
+        assert!(store.is_empty());
+        assert!(store.end(&game).is_none());
+
+        // The game is restarted, while the move is still running
+        store.start(&game, agent.clone());
+        let session = store.take(&game);
+        let restarted = Arc::new(Agent::default());
+        store.start(&game, restarted.clone());
+        store.put(&game, session);
+        let session = store.end(&game).unwrap();
+        assert!(Arc::ptr_eq(session.agent.as_ref().unwrap(), &restarted));
+        assert!(store.is_empty());
+    }
+}
@@ -0,0 +1,69 @@
+//! Zobrist keys for hashing game states.
+//!
+//! Instead of a precomputed table of random numbers, the keys are derived
+//! from the hashed feature with a fast mixing function.
+//! This supports any board size and number of snakes.
+
+use crate::env::Vec2D;
+
+const TURN: u64 = 1;
+const FOOD: u64 = 2;
+const BODY: u64 = 3;
+const HEAD: u64 = 4;
+const LEN: u64 = 5;
+const HEALTH: u64 = 6;
+const TAIL: u64 = 7;
+const STACKED: u64 = 8;
+
+/// SplitMix64 finalizer, see https://prng.di.unimi.it/splitmix64.c
+#[inline]
+fn mix(mut z: u64) -> u64 {
+    z = z.wrapping_add(0x9e37_79b9_7f4a_7c15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    z ^ (z >> 31)
+}
+
+#[inline]
+fn key(kind: u64, snake: usize, value: u64) -> u64 {
+    mix(kind << 56 ^ (snake as u64) << 40 ^ value)
+}
+
+#[inline]
+fn pos(p: Vec2D) -> u64 {
+    (p.x as u16 as u64) << 16 | p.y as u16 as u64
+}
+
+pub fn turn(turn: usize) -> u64 {
+    key(TURN, 0, turn as _)
+}
+
+pub fn food(p: Vec2D) -> u64 {
+    key(FOOD, 0, pos(p))
+}
+
+/// Key of the connection between two different, consecutive body segments,
+/// from `from` towards the head at `to`.
+///
+/// Unlike keys of the cells, the connections preserve the order of the segments
+/// and do not cancel out for stacked segments.
+/// Together with the tail, they determine the body.
+pub fn body(snake: usize, from: Vec2D, to: Vec2D) -> u64 {
+    mix(key(BODY, snake, pos(from)) ^ pos(to))
+}
+
+/// Key of the head, tail, number of segments stacked on the tail, length, and health of a snake.
+pub fn summary(
+    snake: usize,
+    head: Vec2D,
+    tail: Vec2D,
+    stacked: usize,
+    len: usize,
+    health: u8,
+) -> u64 {
+    key(HEAD, snake, pos(head))
+        ^ key(TAIL, snake, pos(tail))
+        ^ key(STACKED, snake, stacked as _)
+        ^ key(LEN, snake, len as _)
+        ^ key(HEALTH, snake, health as _)
+}